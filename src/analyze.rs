@@ -0,0 +1,173 @@
+// Compatibility analysis, reporting everything that prevents a module from
+// being rewritten instead of stopping at the first problem.
+
+use crate::{binary, Error, WASM_PAGE_SIZE};
+use std::fmt;
+use walrus::ir::{DataDrop, Instr, MemoryInit};
+use walrus::{ActiveDataLocation, DataKind, ValType};
+
+/// Something that prevents a module from being rewritten.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Blocker {
+    /// The module declares no memory.
+    NoMemory,
+    /// The module declares more than one memory.
+    MultipleMemories { count: usize },
+    /// The module declares or imports a 64-bit memory.
+    Memory64,
+    /// An active data segment is placed at an offset read from a global.
+    RelativeDataSegment { segment: String },
+    /// A mutable global has a type that cannot be saved per submemory.
+    UnsupportedGlobal { global: String, ty: String },
+    /// An instruction that cannot be rewritten, at byte `offset` in the
+    /// module.
+    UnsupportedInstruction {
+        function: String,
+        offset: Option<u32>,
+        instruction: String,
+    },
+}
+
+impl fmt::Display for Blocker {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Blocker::NoMemory => write!(f, "wasm file has no memory"),
+            Blocker::MultipleMemories { count } => {
+                write!(f, "unsupported multiple memories ({} memories)", count)
+            }
+            Blocker::Memory64 => write!(f, "unsupported 64-bit memory"),
+            Blocker::RelativeDataSegment { segment } => {
+                write!(f, "unsupported relative data segment {}", segment)
+            }
+            Blocker::UnsupportedGlobal { global, ty } => {
+                write!(f, "unsupported mutable global type: {} ({})", ty, global)
+            }
+            Blocker::UnsupportedInstruction {
+                function,
+                offset,
+                instruction,
+            } => {
+                write!(f, "unsupported {} in {}", instruction, function)?;
+                if let Some(offset) = offset {
+                    write!(f, " at offset {:#x}", offset)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The result of analyzing a module with `analyze`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Report {
+    blockers: Vec<Blocker>,
+    min_submemory_size: u32,
+}
+
+impl Report {
+    /// Returns whether the module can be rewritten with a submemory size of
+    /// at least `min_submemory_size`.
+    pub fn is_compatible(&self) -> bool {
+        self.blockers.is_empty()
+    }
+
+    /// Returns everything that prevents the module from being rewritten.
+    pub fn blockers(&self) -> &[Blocker] {
+        &self.blockers
+    }
+
+    /// Returns the smallest submemory size that holds the module's initial
+    /// memory.
+    pub fn min_submemory_size(&self) -> u32 {
+        self.min_submemory_size
+    }
+}
+
+/// Analyzes whether a module can be rewritten.
+pub fn analyze(wasm: &[u8]) -> crate::Result<Report> {
+    // walrus cannot parse 64-bit memories, so nothing else can be analyzed.
+    if binary::has_memory64(wasm) {
+        return Ok(Report {
+            blockers: vec![Blocker::Memory64],
+            min_submemory_size: WASM_PAGE_SIZE,
+        });
+    }
+    let module = walrus::Module::from_buffer(wasm).map_err(Error::Parse)?;
+    Ok(analyze_module(&module))
+}
+
+pub(crate) fn analyze_module(module: &walrus::Module) -> Report {
+    let mut blockers = Vec::new();
+
+    let memories: Vec<_> = module.memories.iter().collect();
+    match memories.len() {
+        0 => blockers.push(Blocker::NoMemory),
+        1 => {}
+        count => blockers.push(Blocker::MultipleMemories { count }),
+    }
+    let initial_pages = memories.first().map_or(0, |memory| memory.initial);
+
+    for data in module.data.iter() {
+        if let DataKind::Active(active) = &data.kind {
+            if let ActiveDataLocation::Relative(_) = active.location {
+                blockers.push(Blocker::RelativeDataSegment {
+                    segment: data
+                        .name
+                        .clone()
+                        .unwrap_or_else(|| format!("data{}", data.id().index())),
+                });
+            }
+        }
+    }
+
+    for global in module.globals.iter().filter(|g| g.mutable) {
+        if !matches!(
+            global.ty,
+            ValType::I32 | ValType::I64 | ValType::F32 | ValType::F64 | ValType::V128
+        ) {
+            blockers.push(Blocker::UnsupportedGlobal {
+                global: global
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| format!("global{}", global.id().index())),
+                ty: format!("{:?}", global.ty),
+            });
+        }
+    }
+
+    for (id, func) in module.funcs.iter_local() {
+        let function = module
+            .funcs
+            .get(id)
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("func{}", id.index()));
+        for (_, block) in func.blocks() {
+            for (instr, instr_loc_id) in &block.instrs {
+                let instruction = match instr {
+                    Instr::MemoryInit(MemoryInit { data, .. })
+                        if !matches!(module.data.get(*data).kind, DataKind::Passive) =>
+                    {
+                        "memory.init of active data segment"
+                    }
+                    Instr::DataDrop(DataDrop { data })
+                        if !matches!(module.data.get(*data).kind, DataKind::Passive) =>
+                    {
+                        "data.drop of active data segment"
+                    }
+                    _ => continue,
+                };
+                blockers.push(Blocker::UnsupportedInstruction {
+                    function: function.clone(),
+                    offset: (!instr_loc_id.is_default()).then(|| instr_loc_id.data()),
+                    instruction: instruction.to_string(),
+                });
+            }
+        }
+    }
+
+    Report {
+        blockers,
+        min_submemory_size: initial_pages.max(1).saturating_mul(WASM_PAGE_SIZE),
+    }
+}
@@ -0,0 +1,129 @@
+// Minimal reading of the raw wasm binary format, for looking at a module
+// without parsing and validating all of it with walrus, and for the few
+// things walrus cannot parse.
+
+const MAGIC: &[u8] = b"\0asm";
+const CUSTOM_SECTION_ID: u8 = 0;
+const IMPORT_SECTION_ID: u8 = 2;
+const MEMORY_SECTION_ID: u8 = 5;
+
+/// Returns whether `wasm` starts with the wasm magic number.
+pub(crate) fn is_wasm(wasm: &[u8]) -> bool {
+    wasm.starts_with(MAGIC)
+}
+
+/// Iterates over the `(id, contents)` of a module's sections, stopping at the
+/// first malformed one.
+pub(crate) fn sections(wasm: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+    // Skip the magic number and version.
+    let mut pos = 8;
+    std::iter::from_fn(move || {
+        let id = *wasm.get(pos)?;
+        pos += 1;
+        let size = read_leb128(wasm, &mut pos)? as usize;
+        let contents = wasm.get(pos..pos.checked_add(size)?)?;
+        pos += size;
+        Some((id, contents))
+    })
+}
+
+/// Returns the contents of the first custom section named `name`.
+pub(crate) fn custom_section<'a>(wasm: &'a [u8], name: &str) -> Option<&'a [u8]> {
+    sections(wasm)
+        .filter(|&(id, _)| id == CUSTOM_SECTION_ID)
+        .find_map(|(_, contents)| {
+            let mut pos = 0;
+            let len = read_leb128(contents, &mut pos)? as usize;
+            let section_name = contents.get(pos..pos.checked_add(len)?)?;
+            (section_name == name.as_bytes()).then(|| &contents[pos + len..])
+        })
+}
+
+/// Returns whether the module declares or imports a 64-bit memory, which
+/// walrus rejects.
+pub(crate) fn has_memory64(wasm: &[u8]) -> bool {
+    sections(wasm).any(|(id, contents)| match id {
+        IMPORT_SECTION_ID => imports_memory64(contents).unwrap_or(false),
+        MEMORY_SECTION_ID => declares_memory64(contents).unwrap_or(false),
+        _ => false,
+    })
+}
+
+fn imports_memory64(data: &[u8]) -> Option<bool> {
+    let mut pos = 0;
+    let count = read_leb128(data, &mut pos)?;
+    for _ in 0..count {
+        // Module and field names.
+        for _ in 0..2 {
+            let len = read_leb128(data, &mut pos)? as usize;
+            pos = pos.checked_add(len)?;
+        }
+        let kind = *data.get(pos)?;
+        pos += 1;
+        match kind {
+            // Function: type index.
+            0x00 => {
+                read_leb128(data, &mut pos)?;
+            }
+            // Table: element type and limits.
+            0x01 => {
+                pos += 1;
+                read_limits(data, &mut pos)?;
+            }
+            0x02 => {
+                if read_limits(data, &mut pos)? {
+                    return Some(true);
+                }
+            }
+            // Global: value type and mutability.
+            0x03 => pos += 2,
+            // Tag: attribute and type index.
+            0x04 => {
+                pos += 1;
+                read_leb128(data, &mut pos)?;
+            }
+            _ => return None,
+        }
+    }
+    Some(false)
+}
+
+fn declares_memory64(data: &[u8]) -> Option<bool> {
+    let mut pos = 0;
+    let count = read_leb128(data, &mut pos)?;
+    for _ in 0..count {
+        if read_limits(data, &mut pos)? {
+            return Some(true);
+        }
+    }
+    Some(false)
+}
+
+/// Skips over a table or memory type's limits, returning whether they are
+/// 64-bit.
+fn read_limits(data: &[u8], pos: &mut usize) -> Option<bool> {
+    let flags = *data.get(*pos)?;
+    *pos += 1;
+    read_leb128(data, pos)?;
+    if flags & 0x01 != 0 {
+        read_leb128(data, pos)?;
+    }
+    Some(flags & 0x04 != 0)
+}
+
+fn read_leb128(data: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *data.get(*pos)?;
+        *pos += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+        shift += 7;
+        if shift >= 64 {
+            return None;
+        }
+    }
+}
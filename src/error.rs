@@ -0,0 +1,144 @@
+// Errors returned when rewriting a module or reading back its metadata.
+
+use crate::{Blocker, SubmemoryId, METADATA_SECTION, WASM_PAGE_SIZE};
+use std::fmt;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The input is not a valid wasm module.
+    Parse(anyhow::Error),
+    /// The submemory size is zero or not a multiple of the page size.
+    InvalidSubmemorySize(u32),
+    /// The headroom size is zero or not a multiple of the page size.
+    InvalidHeadroomSize(u32),
+    /// The headroom cannot hold the records of `max_submemories` submemories.
+    HeadroomTooSmall {
+        headroom_size: u32,
+        max_submemories: u32,
+        required_size: u64,
+    },
+    /// The headroom and the module's initial memory leave no room for
+    /// submemories in the 4 GiB address space.
+    HeadroomTooLarge {
+        headroom_size: u64,
+        initial_pages: u32,
+    },
+    /// The module's initial memory does not fit in a submemory.
+    InitialMemoryTooLarge {
+        initial_pages: u32,
+        submemory_pages: u32,
+    },
+    /// The module cannot be rewritten; see `analyze` for every reason.
+    Incompatible(Blocker),
+    /// The module has already been rewritten.
+    AlreadyRewritten,
+    /// The module already has an export with the name of an injected export.
+    ExportCollision(String),
+    /// The module has no `METADATA_SECTION` custom section.
+    MissingMetadata,
+    /// The `METADATA_SECTION` custom section was written by an incompatible
+    /// version of this crate.
+    UnsupportedMetadataVersion(u32),
+    /// The `METADATA_SECTION` custom section is malformed.
+    InvalidMetadata(String),
+    /// A guest range extends past the end of the submemory.
+    OutOfRange {
+        address: u32,
+        len: u64,
+        submemory_size: u32,
+    },
+    /// A submemory extends past the end of the host's view of memory, e.g.
+    /// because it has not been added yet.
+    PastEndOfMemory { id: SubmemoryId, memory_size: usize },
+}
+
+pub(crate) type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Parse(err) => write!(f, "invalid wasm file: {}", err),
+            Error::InvalidSubmemorySize(size) => write!(
+                f,
+                "submemory size ({} bytes) must be a non-zero multiple of the page size ({} bytes)",
+                size, WASM_PAGE_SIZE
+            ),
+            Error::InvalidHeadroomSize(size) => write!(
+                f,
+                "headroom size ({} bytes) must be a non-zero multiple of the page size ({} bytes)",
+                size, WASM_PAGE_SIZE
+            ),
+            Error::HeadroomTooSmall {
+                headroom_size,
+                max_submemories,
+                required_size,
+            } => write!(
+                f,
+                "headroom size ({} bytes) is too small for {} submemories ({} bytes)",
+                headroom_size, max_submemories, required_size
+            ),
+            Error::HeadroomTooLarge {
+                headroom_size,
+                initial_pages,
+            } => write!(
+                f,
+                "headroom ({} bytes) and initial memory ({} pages) do not fit in memory",
+                headroom_size, initial_pages
+            ),
+            Error::InitialMemoryTooLarge {
+                initial_pages,
+                submemory_pages,
+            } => write!(
+                f,
+                "wasm file's initial memory size ({} pages) is larger than submemory size ({} pages)",
+                initial_pages, submemory_pages
+            ),
+            Error::Incompatible(blocker) => blocker.fmt(f),
+            Error::AlreadyRewritten => write!(f, "wasm file has already been rewritten"),
+            Error::ExportCollision(name) => write!(f, "wasm file already exports {}", name),
+            Error::MissingMetadata => {
+                write!(f, "wasm file has no {} custom section", METADATA_SECTION)
+            }
+            Error::UnsupportedMetadataVersion(version) => write!(
+                f,
+                "unsupported {} section version {}",
+                METADATA_SECTION, version
+            ),
+            Error::InvalidMetadata(reason) => {
+                write!(f, "invalid {} section: {}", METADATA_SECTION, reason)
+            }
+            Error::OutOfRange {
+                address,
+                len,
+                submemory_size,
+            } => write!(
+                f,
+                "guest range {:#x}..{:#x} extends past the end of the submemory ({:#x} bytes)",
+                address,
+                *address as u64 + len,
+                submemory_size
+            ),
+            Error::PastEndOfMemory { id, memory_size } => write!(
+                f,
+                "submemory {} is past the end of memory ({} bytes)",
+                id.index(),
+                memory_size
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Parse(err) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<Blocker> for Error {
+    fn from(blocker: Blocker) -> Self {
+        Error::Incompatible(blocker)
+    }
+}
@@ -0,0 +1,197 @@
+// Host-side API for driving the submemories of a rewritten module.
+//
+// The API is independent of any particular wasm runtime: the runtime only has
+// to provide a way to call the injected exports through the `Guest` trait.
+
+/// An instance of a rewritten module, as seen by the host.
+pub trait Guest {
+    /// Calls the exported function `name` with i32 arguments and returns its
+    /// i32 results.
+    fn call(&mut self, name: &str, args: &[i32]) -> anyhow::Result<Vec<i32>>;
+}
+
+/// Identifies a submemory of a rewritten module instance.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SubmemoryId(u32);
+
+impl SubmemoryId {
+    /// Identifies the submemory at `index`, e.g. as returned by the injected
+    /// `current_submemory` export or read from `__submemory_index` in a host
+    /// function the guest calls.
+    pub fn new(index: u32) -> Self {
+        Self(index)
+    }
+
+    /// Returns the index of the submemory, as passed to the injected exports.
+    pub fn index(self) -> u32 {
+        self.0
+    }
+}
+
+impl From<u32> for SubmemoryId {
+    fn from(index: u32) -> Self {
+        Self(index)
+    }
+}
+
+impl From<SubmemoryId> for u32 {
+    fn from(id: SubmemoryId) -> u32 {
+        id.0
+    }
+}
+
+/// A handle for adding, selecting and removing the submemories of a rewritten
+/// module instance.
+pub struct Submemories<G> {
+    guest: G,
+    export_prefix: String,
+    selected: Option<SubmemoryId>,
+}
+
+impl<G: Guest> Submemories<G> {
+    /// Wraps an instance of a module rewritten with the default export names.
+    pub fn new(guest: G) -> Self {
+        Self {
+            guest,
+            export_prefix: String::new(),
+            selected: None,
+        }
+    }
+
+    /// Sets the prefix the module's injected exports were given with
+    /// `RewriteOptions::export_prefix`.
+    pub fn export_prefix(mut self, export_prefix: impl Into<String>) -> Self {
+        self.export_prefix = export_prefix.into();
+        self
+    }
+
+    pub fn guest(&self) -> &G {
+        &self.guest
+    }
+
+    pub fn guest_mut(&mut self) -> &mut G {
+        &mut self.guest
+    }
+
+    pub fn into_guest(self) -> G {
+        self.guest
+    }
+
+    /// Returns the submemory most recently selected through this handle.
+    pub fn selected(&self) -> Option<SubmemoryId> {
+        self.selected
+    }
+
+    /// Adds a submemory in its initial state.
+    pub fn add(&mut self) -> anyhow::Result<SubmemoryId> {
+        let ret = self.call("add_submemory", &[])?;
+        Self::submemory_result("add_submemory", &ret)
+    }
+
+    /// Adds a copy of the submemory `src`.
+    pub fn fork(&mut self, src: SubmemoryId) -> anyhow::Result<SubmemoryId> {
+        let ret = self.call("fork_submemory", &[src.0 as i32])?;
+        Self::submemory_result("fork_submemory", &ret)
+    }
+
+    /// Overwrites the submemory `dst` with a copy of the submemory `src`.
+    pub fn copy(&mut self, src: SubmemoryId, dst: SubmemoryId) -> anyhow::Result<()> {
+        self.call("copy_submemory", &[src.0 as i32, dst.0 as i32])?;
+        Ok(())
+    }
+
+    /// Restores the submemory `id` to its initial state.
+    pub fn reset(&mut self, id: SubmemoryId) -> anyhow::Result<()> {
+        self.call("reset_submemory", &[id.0 as i32])?;
+        Ok(())
+    }
+
+    /// Removes the submemory `id`, making its slot available to `add` and
+    /// `fork`.
+    pub fn remove(&mut self, id: SubmemoryId) -> anyhow::Result<()> {
+        self.call("remove_submemory", &[id.0 as i32])?;
+        if self.selected == Some(id) {
+            self.selected = None;
+        }
+        Ok(())
+    }
+
+    /// Selects the submemory `id` for subsequent calls into the guest.
+    pub fn select(&mut self, id: SubmemoryId) -> anyhow::Result<()> {
+        self.call("select_submemory", &[id.0 as i32])?;
+        self.selected = Some(id);
+        Ok(())
+    }
+
+    /// Returns the number of submemories added, including removed ones.
+    pub fn count(&mut self) -> anyhow::Result<u32> {
+        Ok(self.call_i32("submemory_count", &[])? as u32)
+    }
+
+    /// Returns the submemory currently selected in the guest, which may have
+    /// been selected by the guest itself rather than through this handle.
+    /// Fails if no submemory is selected, either because none has been yet or
+    /// because the current submemory has been removed.
+    pub fn current(&mut self) -> anyhow::Result<SubmemoryId> {
+        match self.call_i32("current_submemory", &[])? {
+            index if index < 0 => anyhow::bail!("no submemory is selected"),
+            index => Ok(SubmemoryId(index as u32)),
+        }
+    }
+
+    /// Returns the address of the first byte of the submemory `id`.
+    pub fn base_address(&mut self, id: SubmemoryId) -> anyhow::Result<u32> {
+        Ok(self.call_i32("submemory_base", &[id.0 as i32])? as u32)
+    }
+
+    /// Returns the number of pages the submemory `id` has allocated, or
+    /// `None` if it has been removed.
+    pub fn pages(&mut self, id: SubmemoryId) -> anyhow::Result<Option<u32>> {
+        let pages = self.call_i32("submemory_pages", &[id.0 as i32])?;
+        Ok((pages != -1).then_some(pages as u32))
+    }
+
+    /// Returns the highest number of pages the submemory `id` has allocated
+    /// since it was added.
+    pub fn peak_pages(&mut self, id: SubmemoryId) -> anyhow::Result<u32> {
+        Ok(self.call_i32("submemory_peak_pages", &[id.0 as i32])? as u32)
+    }
+
+    /// Runs `f` with the submemory `id` selected, then selects the previously
+    /// selected submemory again, even if `f` fails.
+    pub fn with_selected<R>(
+        &mut self,
+        id: SubmemoryId,
+        f: impl FnOnce(&mut G) -> anyhow::Result<R>,
+    ) -> anyhow::Result<R> {
+        let previous = self.selected;
+        self.select(id)?;
+        let ret = f(&mut self.guest);
+        if let Some(previous) = previous {
+            if previous != id {
+                self.select(previous)?;
+            }
+        }
+        ret
+    }
+
+    fn call(&mut self, name: &str, args: &[i32]) -> anyhow::Result<Vec<i32>> {
+        let name = format!("{}{}", self.export_prefix, name);
+        self.guest.call(&name, args)
+    }
+
+    fn call_i32(&mut self, name: &str, args: &[i32]) -> anyhow::Result<i32> {
+        match *self.call(name, args)? {
+            [value] => Ok(value),
+            ref ret => anyhow::bail!("unexpected result from {}: {:?}", name, ret),
+        }
+    }
+
+    fn submemory_result(name: &str, ret: &[i32]) -> anyhow::Result<SubmemoryId> {
+        match *ret {
+            [-1, -1] => anyhow::bail!("{} failed: no room for another submemory", name),
+            [index, _base_address] => Ok(SubmemoryId(index as u32)),
+            _ => anyhow::bail!("unexpected result from {}: {:?}", name, ret),
+        }
+    }
+}
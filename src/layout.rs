@@ -0,0 +1,131 @@
+// Translation between guest addresses and host offsets in the linear memory
+// of a rewritten module.
+
+use crate::{is_page_multiple, Error, SubmemoryId, WASM_MAX_PAGES, WASM_PAGE_SIZE};
+use std::ops::Range;
+
+/// The placement of the submemories in the linear memory of a rewritten
+/// module.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Layout {
+    submemory_size: u32,
+    headroom_size: u32,
+    initial_pages: u32,
+}
+
+impl Layout {
+    /// Creates the layout of a module rewritten with `submemory_size` and
+    /// `headroom_size` whose original memory had `initial_pages` pages.
+    pub fn new(submemory_size: u32, headroom_size: u32, initial_pages: u32) -> crate::Result<Self> {
+        if !is_page_multiple(submemory_size) {
+            return Err(Error::InvalidSubmemorySize(submemory_size));
+        }
+        if !is_page_multiple(headroom_size) {
+            return Err(Error::InvalidHeadroomSize(headroom_size));
+        }
+        if initial_pages as u64 * WASM_PAGE_SIZE as u64 > submemory_size as u64 {
+            return Err(Error::InitialMemoryTooLarge {
+                initial_pages,
+                submemory_pages: submemory_size / WASM_PAGE_SIZE,
+            });
+        }
+        let start = headroom_size as u64 + initial_pages as u64 * WASM_PAGE_SIZE as u64;
+        if start >= WASM_MAX_PAGES as u64 * WASM_PAGE_SIZE as u64 {
+            return Err(Error::HeadroomTooLarge {
+                headroom_size: headroom_size as u64,
+                initial_pages,
+            });
+        }
+        Ok(Self {
+            submemory_size,
+            headroom_size,
+            initial_pages,
+        })
+    }
+
+    pub fn submemory_size(&self) -> u32 {
+        self.submemory_size
+    }
+
+    pub fn headroom_size(&self) -> u32 {
+        self.headroom_size
+    }
+
+    pub fn initial_pages(&self) -> u32 {
+        self.initial_pages
+    }
+
+    /// Returns the host offset of the first byte of submemory `id`.
+    pub fn base_address(&self, id: SubmemoryId) -> u64 {
+        self.submemories_start() as u64 + id.index() as u64 * self.submemory_size as u64
+    }
+
+    /// Returns the host offsets of the `len` bytes at guest address `address`
+    /// in submemory `id`. Fails if the range extends past the end of the
+    /// submemory.
+    pub fn translate(&self, id: SubmemoryId, address: u32, len: u32) -> crate::Result<Range<u64>> {
+        self.translate_u64(id, address, len as u64)
+    }
+
+    /// Returns the `len` bytes at guest address `address` in submemory `id`
+    /// of `memory`.
+    pub fn read<'a>(
+        &self,
+        memory: &'a [u8],
+        id: SubmemoryId,
+        address: u32,
+        len: u32,
+    ) -> crate::Result<&'a [u8]> {
+        let range = self.host_range(memory, id, address, len as u64)?;
+        Ok(&memory[range])
+    }
+
+    /// Copies `data` to guest address `address` in submemory `id` of
+    /// `memory`.
+    pub fn write(
+        &self,
+        memory: &mut [u8],
+        id: SubmemoryId,
+        address: u32,
+        data: &[u8],
+    ) -> crate::Result<()> {
+        let range = self.host_range(memory, id, address, data.len() as u64)?;
+        memory[range].copy_from_slice(data);
+        Ok(())
+    }
+
+    /// Offset of submemory 0, after the headroom and the initial image.
+    pub(crate) fn submemories_start(&self) -> u32 {
+        self.headroom_size + self.initial_pages * WASM_PAGE_SIZE
+    }
+
+    fn translate_u64(&self, id: SubmemoryId, address: u32, len: u64) -> crate::Result<Range<u64>> {
+        let end = address as u64 + len;
+        if end > self.submemory_size as u64 {
+            return Err(Error::OutOfRange {
+                address,
+                len,
+                submemory_size: self.submemory_size,
+            });
+        }
+        let base_address = self.base_address(id);
+        Ok(base_address + address as u64..base_address + end)
+    }
+
+    fn host_range(
+        &self,
+        memory: &[u8],
+        id: SubmemoryId,
+        address: u32,
+        len: u64,
+    ) -> crate::Result<Range<usize>> {
+        let range = self.translate_u64(id, address, len)?;
+        if range.end > memory.len() as u64 {
+            return Err(Error::PastEndOfMemory {
+                id,
+                memory_size: memory.len(),
+            });
+        }
+        Ok(range.start as usize..range.end as usize)
+    }
+}
@@ -1,158 +1,874 @@
 // TODO figure out if/when entry function runs
-// TODO support more vector instructions (LoadSimd)
-// TODO support memory instructions
 //
 // Memory layout:
-// 1 page submemory bookkeeping ("headroom")
+// H pages submemory bookkeeping ("headroom", sized for the maximum number of
+// submemories, at least 1 page by default)
 // K pages initial memory contents
 // Submemory 0
 // ...
 // Submemory N
+//
+// The headroom holds one fixed-size record per submemory, preceded by a
+// template record that initializes new submemories:
+// Template record
+// Submemory 0 record
+// ...
+// Submemory N record
+//
+// Each record contains the submemory's allocated page count (-1 once the
+// submemory is removed), the index of the next removed submemory in the free
+// list, the highest page count the submemory has reached, a bitmap of the
+// passive data segments it has dropped, and the saved values of every mutable
+// guest global.
+//
+// A rewritten module with a shared memory can only be hosted by a single
+// instance. The records live in the memory, but the submemory count, the head
+// of the free list and the current index are globals of the instance, and
+// every instantiation runs the start function again. A second instance on the
+// same memory would hand out submemory 0 again and overwrite both the template
+// record and the first instance's records.
+mod analyze;
+mod binary;
+mod error;
+mod host;
+mod layout;
+mod metadata;
+mod stats;
+
+pub use analyze::{analyze, Blocker, Report};
+pub use error::Error;
+pub(crate) use error::Result;
+pub use host::{Guest, Submemories, SubmemoryId};
+pub use layout::Layout;
+pub use metadata::{Metadata, METADATA_SECTION};
+use stats::SizingSection;
+pub use stats::{FunctionStats, InstrCounts, RewriteStats};
+
+use std::collections::HashMap;
 use walrus::{
-    ir::*, ActiveDataLocation, FunctionBuilder, FunctionId, GlobalId, InitExpr, LocalFunction,
-    ValType,
+    ir::*, ActiveDataLocation, DataId, FunctionBuilder, FunctionId, GlobalId, InitExpr,
+    InstrSeqBuilder, LocalFunction, MemoryId, ValType,
 };
 
 pub const WASM_PAGE_SIZE: u32 = 65536;
+pub const WASM_MAX_PAGES: u32 = 65536;
+/// Minimum size of the headroom when neither `RewriteOptions::headroom_size`
+/// nor `RewriteOptions::max_submemories` is set.
 pub const HEADROOM_SIZE: u32 = WASM_PAGE_SIZE;
+/// Maximum number of submemories when neither
+/// `RewriteOptions::headroom_size` nor `RewriteOptions::max_submemories` is
+/// set.
+pub const DEFAULT_MAX_SUBMEMORIES: u32 = 16384;
+
+/// How rewritten memory accesses are confined to the current submemory.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BoundsMode {
+    /// Wrap out-of-range addresses around within the submemory. This is the
+    /// fastest mode, but an out-of-range access silently hits another address
+    /// in the same submemory.
+    #[default]
+    Mask,
+    /// Trap on any access that extends past the end of the submemory.
+    Trap,
+}
 
-pub fn rewrite(wasm: &[u8], submemory_size: u32) -> anyhow::Result<Vec<u8>> {
-    let mut module = walrus::Module::from_buffer(wasm)?;
+/// Options controlling how a module is rewritten.
+#[derive(Clone, Debug)]
+pub struct RewriteOptions {
+    submemory_size: u32,
+    bounds_mode: BoundsMode,
+    headroom_size: Option<u32>,
+    max_submemories: Option<u32>,
+    export_prefix: String,
+    export_internals: bool,
+}
 
-    let num_mutable_globals = module.globals.iter().filter(|g| g.mutable).count();
-    if num_mutable_globals > 1 {
-        anyhow::bail!("wasm file has more than one mutable global");
+impl RewriteOptions {
+    /// Creates options for submemories of `submemory_size` bytes, which must
+    /// be a multiple of the page size.
+    pub fn new(submemory_size: u32) -> Self {
+        Self {
+            submemory_size,
+            bounds_mode: BoundsMode::default(),
+            headroom_size: None,
+            max_submemories: None,
+            export_prefix: String::new(),
+            export_internals: false,
+        }
     }
 
-    let base_global = module
-        .globals
-        .add_local(ValType::I32, true, InitExpr::Value(Value::I32(0)));
-    let index_global = module
-        .globals
-        .add_local(ValType::I32, true, InitExpr::Value(Value::I32(0)));
+    /// Sets how memory accesses are confined to the current submemory.
+    pub fn bounds_mode(mut self, bounds_mode: BoundsMode) -> Self {
+        self.bounds_mode = bounds_mode;
+        self
+    }
+
+    /// Sets the size of the bookkeeping area at the start of memory, which
+    /// must be a multiple of the page size. By default it is sized to fit
+    /// `max_submemories`, or `DEFAULT_MAX_SUBMEMORIES` if there is no
+    /// maximum, and is at least `HEADROOM_SIZE`.
+    pub fn headroom_size(mut self, headroom_size: u32) -> Self {
+        self.headroom_size = Some(headroom_size);
+        self
+    }
+
+    /// Sets the maximum number of submemories. `add_submemory` fails once it
+    /// is reached. By default the maximum is as many as fit in the headroom
+    /// if `headroom_size` is set, or `DEFAULT_MAX_SUBMEMORIES` otherwise. The
+    /// maximum is further limited to the submemories that fit in the 4 GiB
+    /// address space.
+    pub fn max_submemories(mut self, max_submemories: u32) -> Self {
+        self.max_submemories = Some(max_submemories);
+        self
+    }
+
+    /// Sets a prefix prepended to the name of every injected export.
+    pub fn export_prefix(mut self, export_prefix: impl Into<String>) -> Self {
+        self.export_prefix = export_prefix.into();
+        self
+    }
+
+    /// Sets whether the internal base address, index and count globals are
+    /// exported as `__submemory_base`, `__submemory_index` and
+    /// `__submemory_count`.
+    pub fn export_internals(mut self, export_internals: bool) -> Self {
+        self.export_internals = export_internals;
+        self
+    }
+
+    fn export_name(&self, name: &str) -> String {
+        format!("{}{}", self.export_prefix, name)
+    }
+}
+
+pub fn rewrite(wasm: &[u8], submemory_size: u32) -> Result<Vec<u8>> {
+    rewrite_with_options(wasm, &RewriteOptions::new(submemory_size))
+}
+
+/// Rewrites `wasm` so that its memory holds the submemories described by
+/// `options`.
+///
+/// Modules with a shared memory are rewritten, but the bookkeeping is not
+/// shared between instances, so the rewritten module must be instantiated
+/// only once per memory.
+pub fn rewrite_with_options(wasm: &[u8], options: &RewriteOptions) -> Result<Vec<u8>> {
+    Ok(rewrite_with_layout(wasm, options)?.0)
+}
+
+/// Like `rewrite_with_options`, also returning the memory layout of the
+/// rewritten module.
+pub fn rewrite_with_layout(wasm: &[u8], options: &RewriteOptions) -> Result<(Vec<u8>, Layout)> {
+    let (output, metadata, _) = rewrite_module(wasm, options)?;
+    Ok((output, *metadata.layout()))
+}
+
+/// Like `rewrite_with_options`, also returning the metadata of the rewritten
+/// module and statistics on what was rewritten.
+pub fn rewrite_with_stats(
+    wasm: &[u8],
+    options: &RewriteOptions,
+) -> Result<(Vec<u8>, Metadata, RewriteStats)> {
+    rewrite_module(wasm, options)
+}
+
+fn rewrite_module(
+    wasm: &[u8],
+    options: &RewriteOptions,
+) -> Result<(Vec<u8>, Metadata, RewriteStats)> {
+    let submemory_size = options.submemory_size;
+    let bounds_mode = options.bounds_mode;
+    if !is_page_multiple(submemory_size) {
+        return Err(Error::InvalidSubmemorySize(submemory_size));
+    }
+    if let Some(headroom_size) = options.headroom_size {
+        if !is_page_multiple(headroom_size) {
+            return Err(Error::InvalidHeadroomSize(headroom_size));
+        }
+    }
+
+    if binary::has_memory64(wasm) {
+        return Err(Error::Incompatible(Blocker::Memory64));
+    }
+    // Preserving the code transform lets the metadata section record the
+    // emitted size of each function for the statistics.
+    let mut module = walrus::ModuleConfig::new()
+        .preserve_code_transform(true)
+        .parse(wasm)
+        .map_err(Error::Parse)?;
+    if module
+        .customs
+        .iter()
+        .any(|(_, section)| section.name() == METADATA_SECTION)
+    {
+        return Err(Error::AlreadyRewritten);
+    }
+    if let Some(blocker) = analyze::analyze_module(&module).blockers().first() {
+        return Err(Error::Incompatible(blocker.clone()));
+    }
+
+    let records = Records::new(&module);
+    // `analyze_module` rejects modules without a memory.
+    let initial_size = module
+        .memories
+        .iter()
+        .next()
+        .map_or(0, |memory| memory.initial as u64 * WASM_PAGE_SIZE as u64);
+    let address_space_size = WASM_MAX_PAGES as u64 * WASM_PAGE_SIZE as u64;
+    // One record per submemory plus the template record.
+    let required_size = |max_submemories: u32| (max_submemories as u64 + 1) * records.size as u64;
+    let (headroom_size, max_submemories) = match (options.headroom_size, options.max_submemories) {
+        (Some(headroom_size), Some(max_submemories)) => {
+            let required_size = required_size(max_submemories);
+            if required_size > headroom_size as u64 {
+                return Err(Error::HeadroomTooSmall {
+                    headroom_size,
+                    max_submemories,
+                    required_size,
+                });
+            }
+            (headroom_size as u64, max_submemories)
+        }
+        (Some(headroom_size), None) => {
+            let max_submemories = (headroom_size / records.size).saturating_sub(1);
+            if max_submemories == 0 {
+                return Err(Error::HeadroomTooSmall {
+                    headroom_size,
+                    max_submemories: 1,
+                    required_size: required_size(1),
+                });
+            }
+            (headroom_size as u64, max_submemories)
+        }
+        (None, Some(max_submemories)) => (
+            required_size(max_submemories).next_multiple_of(WASM_PAGE_SIZE as u64),
+            max_submemories,
+        ),
+        (None, None) => {
+            // As many submemories as fit in the address space, up to the
+            // capacity of a single page of 4-byte records in earlier versions.
+            let max_submemories = DEFAULT_MAX_SUBMEMORIES.min(
+                (address_space_size.saturating_sub(HEADROOM_SIZE as u64 + initial_size)
+                    / submemory_size as u64) as u32,
+            );
+            let headroom_size = required_size(max_submemories)
+                .next_multiple_of(WASM_PAGE_SIZE as u64)
+                .max(HEADROOM_SIZE as u64);
+            (headroom_size, max_submemories)
+        }
+    };
+    // The start of the first submemory must be addressable.
+    if headroom_size + initial_size >= address_space_size {
+        return Err(Error::HeadroomTooLarge {
+            headroom_size,
+            initial_pages: (initial_size / WASM_PAGE_SIZE as u64) as u32,
+        });
+    }
+    let headroom_size = headroom_size as u32;
+
+    // While no submemory is selected, the base address points at the last
+    // `submemory_size` bytes of the address space, so that guest memory
+    // accesses trap instead of landing in the records. Those bytes are only
+    // part of the memory once it has grown to 4 GiB.
+    let no_submemory_base = submemory_size.wrapping_neg() as i32;
+    let base_global = module.globals.add_local(
+        ValType::I32,
+        true,
+        InitExpr::Value(Value::I32(no_submemory_base)),
+    );
+    let index_global = module.globals.add_local(
+        ValType::I32,
+        true,
+        InitExpr::Value(Value::I32(Records::NO_SUBMEMORY)),
+    );
     let count_global = module
         .globals
         .add_local(ValType::I32, true, InitExpr::Value(Value::I32(0)));
+    // Head of the list of removed submemories, or -1 if there are none.
+    let free_global = module
+        .globals
+        .add_local(ValType::I32, true, InitExpr::Value(Value::I32(-1)));
+
+    if options.export_internals {
+        for (name, global) in [
+            ("__submemory_base", base_global),
+            ("__submemory_index", index_global),
+            ("__submemory_count", count_global),
+        ] {
+            add_export(&mut module, &options.export_name(name), global)?;
+        }
+    }
 
     let memory_id;
     let initial_pages;
+    let maximum_pages;
     if let Some(memory) = module.memories.iter_mut().next() {
-        if memory.initial * WASM_PAGE_SIZE > submemory_size {
-            anyhow::bail!("wasm file's initial memory size ({} pages) is larger than submemory size ({} pages)", memory.initial, submemory_size / WASM_PAGE_SIZE);
+        if memory.initial as u64 * WASM_PAGE_SIZE as u64 > submemory_size as u64 {
+            return Err(Error::InitialMemoryTooLarge {
+                initial_pages: memory.initial,
+                submemory_pages: submemory_size / WASM_PAGE_SIZE,
+            });
         }
-        memory.maximum = None;
+        maximum_pages = match memory.maximum {
+            Some(maximum) => maximum.min(submemory_size / WASM_PAGE_SIZE),
+            None => submemory_size / WASM_PAGE_SIZE,
+        };
+        // Shared memories must declare a maximum. They can only be hosted by a
+        // single instance, see the comment at the top of this file.
+        memory.maximum = if memory.shared {
+            Some(WASM_MAX_PAGES)
+        } else {
+            None
+        };
         let data_segment_ids = memory.data_segments.iter().cloned().collect::<Vec<_>>();
         for id in data_segment_ids {
-            match &mut module.data.get_mut(id).kind {
-                walrus::DataKind::Active(active) => match &mut active.location {
-                    ActiveDataLocation::Absolute(ref mut offset) => *offset += HEADROOM_SIZE as u32,
-                    ActiveDataLocation::Relative(_) => {
-                        anyhow::bail!("unsupported relative data segment")
-                    }
-                },
-                _ => {}
+            if let walrus::DataKind::Active(active) = &mut module.data.get_mut(id).kind {
+                match &mut active.location {
+                    ActiveDataLocation::Absolute(ref mut offset) => *offset += headroom_size,
+                    // Rejected by `analyze_module`.
+                    ActiveDataLocation::Relative(_) => unreachable!(),
+                }
             }
         }
-        initial_pages = memory.initial as u32;
+        initial_pages = memory.initial;
         memory_id = memory.id();
-        memory.initial += HEADROOM_SIZE as u32 / WASM_PAGE_SIZE as u32;
+        memory.initial += headroom_size / WASM_PAGE_SIZE;
     } else {
-        anyhow::bail!("wasm file has no memory");
+        return Err(Error::Incompatible(Blocker::NoMemory));
     }
 
+    let layout = Layout::new(submemory_size, headroom_size, initial_pages)?;
+
+    // Submemories past the end of the address space can never be allocated.
+    let max_submemories = max_submemories.min(
+        (address_space_size.saturating_sub(layout.submemories_start() as u64)
+            / submemory_size as u64) as u32,
+    );
+
     let mut exempt_functions = Vec::new();
 
+    // Create a start function that fills in the template record before
+    // running the module's original start function. The original start
+    // function, e.g. `__wasm_init_memory`, runs against the initial image and
+    // the template record, so that everything it initializes is copied into
+    // each new submemory.
+    {
+        let mut func = FunctionBuilder::new(&mut module.types, &[], &[]);
+        // The template record is at address 0, the default value of a local.
+        let template = module.locals.add(ValType::I32);
+        let mut body = func.func_body();
+        // template.allocated_pages = template.peak_pages = initial_pages
+        for offset in [0, Records::PEAK_PAGES_OFFSET] {
+            body.i32_const(0).i32_const(initial_pages as i32).store(
+                memory_id,
+                StoreKind::I32 { atomic: false },
+                MemArg { align: 4, offset },
+            );
+        }
+        if let Some(start) = module.start {
+            // records[-1] is the template record. Record addresses of the
+            // current submemory are computed with wrapping i32 arithmetic
+            // rather than memarg offsets so that this lands at address 0.
+            body.i32_const(-1)
+                .global_set(index_global)
+                .i32_const(headroom_size as i32)
+                .global_set(base_global)
+                .call(start)
+                .i32_const(Records::NO_SUBMEMORY)
+                .global_set(index_global)
+                .i32_const(no_submemory_base)
+                .global_set(base_global);
+        }
+        // template.globals = globals, including those set by the original
+        // start function
+        records.save_globals(&mut body, memory_id, template, 0);
+        let id = func.finish(vec![], &mut module.funcs);
+        module.start = Some(id);
+        exempt_functions.push(id);
+    }
+
     // Create a select_submemory(index: i32) function.
     {
         let mut func = FunctionBuilder::new(&mut module.types, &[ValType::I32], &[]);
         let index = module.locals.add(ValType::I32);
-        func.func_body()
-            .local_get(index)
+        let record = module.locals.add(ValType::I32);
+        let mut body = func.func_body();
+        records.check_live(&mut body, memory_id, count_global, index);
+        // if index_global != NO_SUBMEMORY { records[index_global].globals = globals }
+        body.global_get(index_global)
+            .i32_const(Records::NO_SUBMEMORY)
+            .binop(BinaryOp::I32Ne)
+            .if_else(
+                None,
+                |then| {
+                    then.global_get(index_global)
+                        .i32_const(records.size as i32)
+                        .binop(BinaryOp::I32Mul)
+                        .local_set(record);
+                    records.save_globals(then, memory_id, record, records.size);
+                },
+                |_| {},
+            );
+        body.local_get(index)
             .global_set(index_global)
             .local_get(index)
             .i32_const(submemory_size as i32)
             .binop(BinaryOp::I32Mul)
-            .i32_const((HEADROOM_SIZE + initial_pages * WASM_PAGE_SIZE) as i32)
+            .i32_const(layout.submemories_start() as i32)
             .binop(BinaryOp::I32Add)
             .global_set(base_global);
+        // globals = records[index].globals
+        body.local_get(index)
+            .i32_const(records.size as i32)
+            .binop(BinaryOp::I32Mul)
+            .local_set(record);
+        records.restore_globals(&mut body, memory_id, record, records.size);
         let id = func.finish(vec![index], &mut module.funcs);
-        module.exports.add("select_submemory", id);
+        add_export(&mut module, &options.export_name("select_submemory"), id)?;
         exempt_functions.push(id);
     }
 
     // Create an add_submemory() -> (index: i32, base_address: i32) function.
-    {
+    // It returns (-1, -1) without changing any state if there is no room for
+    // another submemory.
+    let add_submemory = {
         let mut func = FunctionBuilder::new(&mut module.types, &[], &[ValType::I32, ValType::I32]);
+        let index = module.locals.add(ValType::I32);
         let base_address = module.locals.add(ValType::I32);
+        let prev_pages = module.locals.add(ValType::I32);
         func.func_body()
-            // prev_pages = memory.grow(submemory_size / WASM_PAGE_SIZE)
-            .i32_const((submemory_size / WASM_PAGE_SIZE) as i32)
-            .memory_grow(memory_id)
-            // base_address = prev_pages * WASM_PAGE_SIZE
-            .i32_const(WASM_PAGE_SIZE as i32)
-            .binop(BinaryOp::I32Mul)
-            .local_tee(base_address)
-            // memory.copy(base_address, HEADROOM_SIZE, initial_pages * WASM_PAGE_SIZE)
-            .i32_const(HEADROOM_SIZE as i32)
+            .global_get(free_global)
+            .i32_const(-1)
+            .binop(BinaryOp::I32Ne)
+            .if_else(
+                None,
+                |then| {
+                    // Reuse the most recently removed submemory.
+                    // index = free; free = records[index].next_free
+                    then.global_get(free_global)
+                        .local_tee(index)
+                        .i32_const(records.size as i32)
+                        .binop(BinaryOp::I32Mul)
+                        .load(
+                            memory_id,
+                            LoadKind::I32 { atomic: false },
+                            MemArg {
+                                align: 4,
+                                offset: records.size + Records::NEXT_FREE_OFFSET,
+                            },
+                        )
+                        .global_set(free_global)
+                        // base_address = index * submemory_size + submemories_start
+                        .local_get(index)
+                        .i32_const(submemory_size as i32)
+                        .binop(BinaryOp::I32Mul)
+                        .i32_const(layout.submemories_start() as i32)
+                        .binop(BinaryOp::I32Add)
+                        .local_tee(base_address)
+                        // memory.fill(base_address + initial_pages * WASM_PAGE_SIZE, 0, submemory_size - initial_pages * WASM_PAGE_SIZE)
+                        .i32_const((initial_pages * WASM_PAGE_SIZE) as i32)
+                        .binop(BinaryOp::I32Add)
+                        .i32_const(0)
+                        .i32_const((submemory_size - initial_pages * WASM_PAGE_SIZE) as i32)
+                        .memory_fill(memory_id);
+                },
+                |else_| {
+                    // if count >= max_submemories { return (-1, -1) }
+                    else_
+                        .global_get(count_global)
+                        .i32_const(max_submemories as i32)
+                        .binop(BinaryOp::I32GeU)
+                        .if_else(
+                            None,
+                            |then| {
+                                then.i32_const(-1).i32_const(-1).return_();
+                            },
+                            |_| {},
+                        )
+                        // prev_pages = memory.grow(submemory_size / WASM_PAGE_SIZE)
+                        .i32_const((submemory_size / WASM_PAGE_SIZE) as i32)
+                        .memory_grow(memory_id)
+                        .local_tee(prev_pages)
+                        // if prev_pages == -1 { return (-1, -1) }
+                        .i32_const(-1)
+                        .binop(BinaryOp::I32Eq)
+                        .if_else(
+                            None,
+                            |then| {
+                                then.i32_const(-1).i32_const(-1).return_();
+                            },
+                            |_| {},
+                        )
+                        // base_address = prev_pages * WASM_PAGE_SIZE
+                        .local_get(prev_pages)
+                        .i32_const(WASM_PAGE_SIZE as i32)
+                        .binop(BinaryOp::I32Mul)
+                        .local_set(base_address)
+                        // index = count++
+                        .global_get(count_global)
+                        .local_tee(index)
+                        .i32_const(1)
+                        .binop(BinaryOp::I32Add)
+                        .global_set(count_global);
+                },
+            )
+            // memory.copy(base_address, headroom_size, initial_pages * WASM_PAGE_SIZE)
+            .local_get(base_address)
+            .i32_const(headroom_size as i32)
             .i32_const((initial_pages * WASM_PAGE_SIZE) as i32)
             .memory_copy(memory_id, memory_id)
-            // allocated_pages[count] = initial_pages
-            .global_get(count_global)
-            .i32_const(4)
+            // records[index] = template
+            .local_get(index)
+            .i32_const(records.size as i32)
+            .binop(BinaryOp::I32Mul)
+            .i32_const(records.size as i32)
+            .binop(BinaryOp::I32Add)
+            .i32_const(0)
+            .i32_const(records.size as i32)
+            .memory_copy(memory_id, memory_id)
+            // return (index, base_address)
+            .local_get(index)
+            .local_get(base_address);
+        let id = func.finish(vec![], &mut module.funcs);
+        add_export(&mut module, &options.export_name("add_submemory"), id)?;
+        exempt_functions.push(id);
+        id
+    };
+
+    // Create a copy_submemory(src: i32, dst: i32) function that overwrites a
+    // submemory with the contents, allocated pages and globals of another.
+    let copy_submemory = {
+        let mut func = FunctionBuilder::new(&mut module.types, &[ValType::I32, ValType::I32], &[]);
+        let src = module.locals.add(ValType::I32);
+        let dst = module.locals.add(ValType::I32);
+        let src_record = module.locals.add(ValType::I32);
+        let dst_record = module.locals.add(ValType::I32);
+        let peak_pages = module.locals.add(ValType::I32);
+        let mut body = func.func_body();
+        records.check_live(&mut body, memory_id, count_global, src);
+        records.check_live(&mut body, memory_id, count_global, dst);
+        body
+            // if src == dst { return }
+            .local_get(src)
+            .local_get(dst)
+            .binop(BinaryOp::I32Eq)
+            .if_else(
+                None,
+                |then| {
+                    then.return_();
+                },
+                |_| {},
+            )
+            .local_get(src)
+            .i32_const(records.size as i32)
+            .binop(BinaryOp::I32Mul)
+            .local_set(src_record)
+            .local_get(dst)
+            .i32_const(records.size as i32)
             .binop(BinaryOp::I32Mul)
-            .i32_const(initial_pages as i32)
+            .local_set(dst_record)
+            // if src == index_global { records[src].globals = globals }
+            .local_get(src)
+            .global_get(index_global)
+            .binop(BinaryOp::I32Eq)
+            .if_else(
+                None,
+                |then| {
+                    records.save_globals(then, memory_id, src_record, records.size);
+                },
+                |_| {},
+            )
+            // memory.copy(dst * submemory_size + submemories_start,
+            //             src * submemory_size + submemories_start,
+            //             submemory_size)
+            .local_get(dst)
+            .i32_const(submemory_size as i32)
+            .binop(BinaryOp::I32Mul)
+            .i32_const(layout.submemories_start() as i32)
+            .binop(BinaryOp::I32Add)
+            .local_get(src)
+            .i32_const(submemory_size as i32)
+            .binop(BinaryOp::I32Mul)
+            .i32_const(layout.submemories_start() as i32)
+            .binop(BinaryOp::I32Add)
+            .i32_const(submemory_size as i32)
+            .memory_copy(memory_id, memory_id)
+            // peak_pages = records[dst].peak_pages
+            .local_get(dst_record)
+            .load(
+                memory_id,
+                LoadKind::I32 { atomic: false },
+                MemArg {
+                    align: 4,
+                    offset: records.size + Records::PEAK_PAGES_OFFSET,
+                },
+            )
+            .local_set(peak_pages)
+            // records[dst] = records[src]
+            .local_get(dst_record)
+            .i32_const(records.size as i32)
+            .binop(BinaryOp::I32Add)
+            .local_get(src_record)
+            .i32_const(records.size as i32)
+            .binop(BinaryOp::I32Add)
+            .i32_const(records.size as i32)
+            .memory_copy(memory_id, memory_id);
+        records.update_peak_pages(&mut body, memory_id, dst_record, records.size, peak_pages);
+        body
+            // if dst == index_global { globals = records[dst].globals }
+            .local_get(dst)
+            .global_get(index_global)
+            .binop(BinaryOp::I32Eq)
+            .if_else(
+                None,
+                |then| {
+                    records.restore_globals(then, memory_id, dst_record, records.size);
+                },
+                |_| {},
+            );
+        let id = func.finish(vec![src, dst], &mut module.funcs);
+        add_export(&mut module, &options.export_name("copy_submemory"), id)?;
+        exempt_functions.push(id);
+        id
+    };
+
+    // Create a fork_submemory(src: i32) -> (index: i32, base_address: i32)
+    // function that adds a copy of an existing submemory. Like add_submemory,
+    // it returns (-1, -1) if there is no room for another submemory.
+    {
+        let mut func = FunctionBuilder::new(
+            &mut module.types,
+            &[ValType::I32],
+            &[ValType::I32, ValType::I32],
+        );
+        let src = module.locals.add(ValType::I32);
+        let index = module.locals.add(ValType::I32);
+        let base_address = module.locals.add(ValType::I32);
+        let mut body = func.func_body();
+        records.check_live(&mut body, memory_id, count_global, src);
+        body
+            // (index, base_address) = add_submemory()
+            .call(add_submemory)
+            .local_set(base_address)
+            .local_tee(index)
+            // if index == -1 { return (-1, -1) }
+            .i32_const(-1)
+            .binop(BinaryOp::I32Eq)
+            .if_else(
+                None,
+                |then| {
+                    then.i32_const(-1).i32_const(-1).return_();
+                },
+                |_| {},
+            )
+            // copy_submemory(src, index)
+            .local_get(src)
+            .local_get(index)
+            .call(copy_submemory)
+            // return (index, base_address)
+            .local_get(index)
+            .local_get(base_address);
+        let id = func.finish(vec![src], &mut module.funcs);
+        add_export(&mut module, &options.export_name("fork_submemory"), id)?;
+        exempt_functions.push(id);
+    }
+
+    // Create a remove_submemory(index: i32) function that pushes the
+    // submemory onto the free list for reuse by add_submemory.
+    {
+        let mut func = FunctionBuilder::new(&mut module.types, &[ValType::I32], &[]);
+        let index = module.locals.add(ValType::I32);
+        let record = module.locals.add(ValType::I32);
+        let mut body = func.func_body();
+        records.check_live(&mut body, memory_id, count_global, index);
+        // records[index].allocated_pages = -1
+        body.local_get(index)
+            .i32_const(records.size as i32)
+            .binop(BinaryOp::I32Mul)
+            .local_tee(record)
+            .i32_const(-1)
             .store(
                 memory_id,
                 StoreKind::I32 { atomic: false },
                 MemArg {
                     align: 4,
-                    offset: 0,
+                    offset: records.size,
                 },
             )
-            // return (count++, base_address)
-            .global_get(count_global)
-            .local_get(base_address)
-            .global_get(count_global)
-            .i32_const(1)
-            .binop(BinaryOp::I32Add)
-            .global_set(count_global);
-        let id = func.finish(vec![], &mut module.funcs);
-        module.exports.add("add_submemory", id);
+            // records[index].next_free = free
+            .local_get(record)
+            .global_get(free_global)
+            .store(
+                memory_id,
+                StoreKind::I32 { atomic: false },
+                MemArg {
+                    align: 4,
+                    offset: records.size + Records::NEXT_FREE_OFFSET,
+                },
+            )
+            // free = index
+            .local_get(index)
+            .global_set(free_global)
+            // if index == index_global {
+            //     index_global = NO_SUBMEMORY; base_global = no_submemory_base
+            // }
+            .local_get(index)
+            .global_get(index_global)
+            .binop(BinaryOp::I32Eq)
+            .if_else(
+                None,
+                |then| {
+                    then.i32_const(Records::NO_SUBMEMORY)
+                        .global_set(index_global)
+                        .i32_const(no_submemory_base)
+                        .global_set(base_global);
+                },
+                |_| {},
+            );
+        let id = func.finish(vec![index], &mut module.funcs);
+        add_export(&mut module, &options.export_name("remove_submemory"), id)?;
         exempt_functions.push(id);
     }
 
-    // Create a reset_submemory(index: i32) function.
+    // Create a reset_submemory(index: i32) function that restores a
+    // submemory to the state of a newly added one.
     {
         let mut func = FunctionBuilder::new(&mut module.types, &[ValType::I32], &[]);
         let index = module.locals.add(ValType::I32);
-        func.func_body()
-            // base_address = allocated_pages[index] * WASM_PAGE_SIZE
+        let base_address = module.locals.add(ValType::I32);
+        let record = module.locals.add(ValType::I32);
+        let peak_pages = module.locals.add(ValType::I32);
+        let mut body = func.func_body();
+        records.check_live(&mut body, memory_id, count_global, index);
+        body
+            // peak_pages = records[index].peak_pages
+            .local_get(index)
+            .i32_const(records.size as i32)
+            .binop(BinaryOp::I32Mul)
+            .local_tee(record)
+            .load(
+                memory_id,
+                LoadKind::I32 { atomic: false },
+                MemArg {
+                    align: 4,
+                    offset: records.size + Records::PEAK_PAGES_OFFSET,
+                },
+            )
+            .local_set(peak_pages)
+            // base_address = index * submemory_size + submemories_start
             .local_get(index)
             .i32_const(submemory_size as i32)
             .binop(BinaryOp::I32Mul)
-            .i32_const((HEADROOM_SIZE + initial_pages * WASM_PAGE_SIZE) as i32)
+            .i32_const(layout.submemories_start() as i32)
             .binop(BinaryOp::I32Add)
-            // memory.copy(base_address, HEADROOM_SIZE, initial_pages * WASM_PAGE_SIZE)
-            .i32_const(HEADROOM_SIZE as i32)
+            .local_tee(base_address)
+            // memory.copy(base_address, headroom_size, initial_pages * WASM_PAGE_SIZE)
+            .i32_const(headroom_size as i32)
+            .i32_const((initial_pages * WASM_PAGE_SIZE) as i32)
+            .memory_copy(memory_id, memory_id)
+            // memory.fill(base_address + initial_pages * WASM_PAGE_SIZE, 0, submemory_size - initial_pages * WASM_PAGE_SIZE)
+            .local_get(base_address)
             .i32_const((initial_pages * WASM_PAGE_SIZE) as i32)
+            .binop(BinaryOp::I32Add)
+            .i32_const(0)
+            .i32_const((submemory_size - initial_pages * WASM_PAGE_SIZE) as i32)
+            .memory_fill(memory_id)
+            // records[index] = template
+            .local_get(record)
+            .i32_const(records.size as i32)
+            .binop(BinaryOp::I32Add)
+            .i32_const(0)
+            .i32_const(records.size as i32)
             .memory_copy(memory_id, memory_id);
+        records.update_peak_pages(&mut body, memory_id, record, records.size, peak_pages);
+        body
+            // if index == index_global { globals = records[index].globals }
+            .local_get(index)
+            .global_get(index_global)
+            .binop(BinaryOp::I32Eq)
+            .if_else(
+                None,
+                |then| {
+                    records.restore_globals(then, memory_id, record, records.size);
+                },
+                |_| {},
+            );
+        let id = func.finish(vec![index], &mut module.funcs);
+        add_export(&mut module, &options.export_name("reset_submemory"), id)?;
+        exempt_functions.push(id);
+    }
+
+    // Create submemory_count() -> i32 and current_submemory() -> i32
+    // functions.
+    for (name, global) in [
+        ("submemory_count", count_global),
+        ("current_submemory", index_global),
+    ] {
+        let mut func = FunctionBuilder::new(&mut module.types, &[], &[ValType::I32]);
+        func.func_body().global_get(global);
+        let id = func.finish(vec![], &mut module.funcs);
+        add_export(&mut module, &options.export_name(name), id)?;
+        exempt_functions.push(id);
+    }
+
+    // Create a submemory_base(index: i32) -> i32 function.
+    {
+        let mut func = FunctionBuilder::new(&mut module.types, &[ValType::I32], &[ValType::I32]);
+        let index = module.locals.add(ValType::I32);
+        let mut body = func.func_body();
+        Records::check_added(&mut body, count_global, index);
+        // return index * submemory_size + submemories_start
+        body.local_get(index)
+            .i32_const(submemory_size as i32)
+            .binop(BinaryOp::I32Mul)
+            .i32_const(layout.submemories_start() as i32)
+            .binop(BinaryOp::I32Add);
+        let id = func.finish(vec![index], &mut module.funcs);
+        add_export(&mut module, &options.export_name("submemory_base"), id)?;
+        exempt_functions.push(id);
+    }
+
+    // Create submemory_pages(index: i32) -> i32 and
+    // submemory_peak_pages(index: i32) -> i32 functions. The page count of a
+    // removed submemory is -1.
+    for (name, offset) in [
+        ("submemory_pages", 0),
+        ("submemory_peak_pages", Records::PEAK_PAGES_OFFSET),
+    ] {
+        let mut func = FunctionBuilder::new(&mut module.types, &[ValType::I32], &[ValType::I32]);
+        let index = module.locals.add(ValType::I32);
+        let mut body = func.func_body();
+        Records::check_added(&mut body, count_global, index);
+        body.local_get(index)
+            .i32_const(records.size as i32)
+            .binop(BinaryOp::I32Mul)
+            .load(
+                memory_id,
+                LoadKind::I32 { atomic: false },
+                MemArg {
+                    align: 4,
+                    offset: records.size + offset,
+                },
+            );
         let id = func.finish(vec![index], &mut module.funcs);
-        module.exports.add("reset_submemory", id);
+        add_export(&mut module, &options.export_name(name), id)?;
         exempt_functions.push(id);
     }
 
     // Create a fake_memory_grow(i32) -> i32 function.
-    // TODO return -1 if the submemory is full
     let fake_memory_grow = {
         let mut func = FunctionBuilder::new(&mut module.types, &[ValType::I32], &[ValType::I32]);
         let delta_pages = module.locals.add(ValType::I32);
         let addr = module.locals.add(ValType::I32);
         let prev_pages = module.locals.add(ValType::I32);
-        func.func_body()
-            // addr = &allocated_pages[index]
+        let peak_pages = module.locals.add(ValType::I32);
+        let mut body = func.func_body();
+        body
+            // addr = &records[index].allocated_pages
             .global_get(index_global)
-            .i32_const(4)
+            .i32_const(records.size as i32)
             .binop(BinaryOp::I32Mul)
+            .i32_const(records.size as i32)
+            .binop(BinaryOp::I32Add)
             .local_tee(addr)
             // prev_pages = *addr
             .load(
@@ -164,7 +880,20 @@ pub fn rewrite(wasm: &[u8], submemory_size: u32) -> anyhow::Result<Vec<u8>> {
                 },
             )
             .local_set(prev_pages)
-            // allocated_pages[index] += delta_pages
+            // if maximum_pages - prev_pages < delta_pages { return -1 }
+            .i32_const(maximum_pages as i32)
+            .local_get(prev_pages)
+            .binop(BinaryOp::I32Sub)
+            .local_get(delta_pages)
+            .binop(BinaryOp::I32LtU)
+            .if_else(
+                None,
+                |then| {
+                    then.i32_const(-1).return_();
+                },
+                |_| {},
+            )
+            // records[index].allocated_pages += delta_pages
             .local_get(addr)
             .local_get(prev_pages)
             .local_get(delta_pages)
@@ -177,8 +906,20 @@ pub fn rewrite(wasm: &[u8], submemory_size: u32) -> anyhow::Result<Vec<u8>> {
                     offset: 0,
                 },
             )
-            // return prev_pages
-            .local_get(prev_pages);
+            // peak_pages = records[index].peak_pages
+            .local_get(addr)
+            .load(
+                memory_id,
+                LoadKind::I32 { atomic: false },
+                MemArg {
+                    align: 4,
+                    offset: Records::PEAK_PAGES_OFFSET,
+                },
+            )
+            .local_set(peak_pages);
+        records.update_peak_pages(&mut body, memory_id, addr, 0, peak_pages);
+        // return prev_pages
+        body.local_get(prev_pages);
         let id = func.finish(vec![delta_pages], &mut module.funcs);
         exempt_functions.push(id);
         id
@@ -188,9 +929,12 @@ pub fn rewrite(wasm: &[u8], submemory_size: u32) -> anyhow::Result<Vec<u8>> {
     let fake_memory_size = {
         let mut func = FunctionBuilder::new(&mut module.types, &[], &[ValType::I32]);
         func.func_body()
+            // records[index].allocated_pages
             .global_get(index_global)
-            .i32_const(4)
+            .i32_const(records.size as i32)
             .binop(BinaryOp::I32Mul)
+            .i32_const(records.size as i32)
+            .binop(BinaryOp::I32Add)
             .load(
                 memory_id,
                 LoadKind::I32 { atomic: false },
@@ -204,147 +948,404 @@ pub fn rewrite(wasm: &[u8], submemory_size: u32) -> anyhow::Result<Vec<u8>> {
         id
     };
 
+    // Create a fake_memory_fill(dst: i32, value: i32, len: i32) function.
+    let fake_memory_fill = {
+        let mut func = FunctionBuilder::new(
+            &mut module.types,
+            &[ValType::I32, ValType::I32, ValType::I32],
+            &[],
+        );
+        let dst = module.locals.add(ValType::I32);
+        let value = module.locals.add(ValType::I32);
+        let len = module.locals.add(ValType::I32);
+        let mut body = func.func_body();
+        confine_range(&mut body, dst, len, submemory_size, bounds_mode);
+        // memory.fill(base + dst, value, len)
+        body.local_get(dst)
+            .global_get(base_global)
+            .binop(BinaryOp::I32Add)
+            .local_get(value)
+            .local_get(len)
+            .memory_fill(memory_id);
+        let id = func.finish(vec![dst, value, len], &mut module.funcs);
+        exempt_functions.push(id);
+        id
+    };
+
+    // Create a fake_memory_copy(dst: i32, src: i32, len: i32) function.
+    let fake_memory_copy = {
+        let mut func = FunctionBuilder::new(
+            &mut module.types,
+            &[ValType::I32, ValType::I32, ValType::I32],
+            &[],
+        );
+        let dst = module.locals.add(ValType::I32);
+        let src = module.locals.add(ValType::I32);
+        let len = module.locals.add(ValType::I32);
+        let mut body = func.func_body();
+        confine_range(&mut body, dst, len, submemory_size, bounds_mode);
+        confine_range(&mut body, src, len, submemory_size, bounds_mode);
+        // memory.copy(base + dst, base + src, len)
+        body.local_get(dst)
+            .global_get(base_global)
+            .binop(BinaryOp::I32Add)
+            .local_get(src)
+            .global_get(base_global)
+            .binop(BinaryOp::I32Add)
+            .local_get(len)
+            .memory_copy(memory_id, memory_id);
+        let id = func.finish(vec![dst, src, len], &mut module.funcs);
+        exempt_functions.push(id);
+        id
+    };
+
+    // Create a fake_data_drop(segment: i32) function that marks a passive
+    // data segment as dropped in the current submemory only.
+    let fake_data_drop = {
+        let mut func = FunctionBuilder::new(&mut module.types, &[ValType::I32], &[]);
+        let segment = module.locals.add(ValType::I32);
+        let addr = module.locals.add(ValType::I32);
+        func.func_body()
+            // addr = &records[index].dropped[segment / 32]
+            .global_get(index_global)
+            .i32_const(records.size as i32)
+            .binop(BinaryOp::I32Mul)
+            .i32_const((records.size + Records::DROPPED_OFFSET) as i32)
+            .binop(BinaryOp::I32Add)
+            .local_get(segment)
+            .i32_const(5)
+            .binop(BinaryOp::I32ShrU)
+            .i32_const(4)
+            .binop(BinaryOp::I32Mul)
+            .binop(BinaryOp::I32Add)
+            .local_tee(addr)
+            // *addr |= 1 << (segment % 32)
+            .local_get(addr)
+            .load(
+                memory_id,
+                LoadKind::I32 { atomic: false },
+                MemArg {
+                    align: 4,
+                    offset: 0,
+                },
+            )
+            .i32_const(1)
+            .local_get(segment)
+            .binop(BinaryOp::I32Shl)
+            .binop(BinaryOp::I32Or)
+            .store(
+                memory_id,
+                StoreKind::I32 { atomic: false },
+                MemArg {
+                    align: 4,
+                    offset: 0,
+                },
+            );
+        let id = func.finish(vec![segment], &mut module.funcs);
+        exempt_functions.push(id);
+        id
+    };
+
+    // Create a fake_memory_init(dst: i32, src: i32, len: i32) function for
+    // each passive data segment.
+    let mut passive_data = HashMap::new();
+    for (segment, &data) in records.passive_data.iter().enumerate() {
+        let segment = segment as u32;
+        let mut func = FunctionBuilder::new(
+            &mut module.types,
+            &[ValType::I32, ValType::I32, ValType::I32],
+            &[],
+        );
+        let dst = module.locals.add(ValType::I32);
+        let src = module.locals.add(ValType::I32);
+        let len = module.locals.add(ValType::I32);
+        let mut body = func.func_body();
+        // The destination is checked even if the segment has been dropped.
+        confine_range(&mut body, dst, len, submemory_size, bounds_mode);
+        // A dropped segment behaves as if it were empty.
+        body.global_get(index_global)
+            .i32_const(records.size as i32)
+            .binop(BinaryOp::I32Mul)
+            .i32_const((records.size + Records::DROPPED_OFFSET + segment / 32 * 4) as i32)
+            .binop(BinaryOp::I32Add)
+            .load(
+                memory_id,
+                LoadKind::I32 { atomic: false },
+                MemArg {
+                    align: 4,
+                    offset: 0,
+                },
+            )
+            .i32_const(1 << (segment % 32))
+            .binop(BinaryOp::I32And)
+            .if_else(
+                None,
+                |then| {
+                    then.local_get(src)
+                        .local_get(len)
+                        .binop(BinaryOp::I32Or)
+                        .if_else(
+                            None,
+                            |then| {
+                                then.unreachable();
+                            },
+                            |_| {},
+                        )
+                        .return_();
+                },
+                |_| {},
+            );
+        // memory.init(base + dst, src, len)
+        body.local_get(dst)
+            .global_get(base_global)
+            .binop(BinaryOp::I32Add)
+            .local_get(src)
+            .local_get(len)
+            .memory_init(memory_id, data);
+        let id = func.finish(vec![dst, src, len], &mut module.funcs);
+        exempt_functions.push(id);
+        passive_data.insert(
+            data,
+            PassiveData {
+                segment,
+                fake_memory_init: id,
+            },
+        );
+    }
+
     let saved_values = SavedValues::new(&mut module);
     let context = Context {
         base_global,
         submemory_size,
+        bounds_mode,
         saved_values,
         fake_memory_grow,
         fake_memory_size,
+        fake_memory_fill,
+        fake_memory_copy,
+        fake_data_drop,
+        passive_data,
     };
-    for (id, func) in module.funcs.iter_local_mut() {
+    let mut stats = RewriteStats::default();
+    for func in module.funcs.iter_mut() {
+        let id = func.id();
         if exempt_functions.contains(&id) {
             continue;
         }
-        rewrite_function(func, &context)?;
+        if let walrus::FunctionKind::Local(local) = &mut func.kind {
+            let counts = rewrite_function(local, &context);
+            let name = func
+                .name
+                .clone()
+                .unwrap_or_else(|| format!("func{}", id.index()));
+            stats.add_function(id, name, counts);
+        }
     }
 
-    Ok(module.emit_wasm())
+    let metadata = Metadata::new(
+        layout,
+        max_submemories,
+        bounds_mode,
+        options.export_internals,
+        options.export_prefix.clone(),
+    );
+    let metadata_section = SizingSection::new(metadata.encode());
+    let function_sizes = metadata_section.function_sizes();
+    module.customs.add(metadata_section);
+
+    let output = module.emit_wasm();
+    stats.set_function_sizes(&function_sizes.lock().unwrap());
+    stats.set_sizes(wasm, &output);
+    Ok((output, metadata, stats))
+}
+
+/// Exports `item` as `name`, failing if the module already has an export of
+/// that name, e.g. because it has already been rewritten.
+fn add_export(
+    module: &mut walrus::Module,
+    name: &str,
+    item: impl Into<walrus::ExportItem>,
+) -> Result<()> {
+    if module.exports.iter().any(|export| export.name == name) {
+        return Err(Error::ExportCollision(name.to_string()));
+    }
+    module.exports.add(name, item);
+    Ok(())
+}
+
+/// Returns whether `size` is a non-zero multiple of the page size.
+pub(crate) fn is_page_multiple(size: u32) -> bool {
+    size != 0 && size.is_multiple_of(WASM_PAGE_SIZE)
 }
 
 struct Context {
     base_global: GlobalId,
     submemory_size: u32,
+    bounds_mode: BoundsMode,
     saved_values: SavedValues,
     fake_memory_grow: FunctionId,
     fake_memory_size: FunctionId,
+    fake_memory_fill: FunctionId,
+    fake_memory_copy: FunctionId,
+    fake_data_drop: FunctionId,
+    passive_data: HashMap<DataId, PassiveData>,
+}
+
+struct PassiveData {
+    segment: u32,
+    fake_memory_init: FunctionId,
+}
+
+/// Confines the address in `addr` to the submemory according to
+/// `bounds_mode` and traps if the `len` bytes starting there extend past the
+/// end of the submemory.
+fn confine_range(
+    body: &mut InstrSeqBuilder,
+    addr: LocalId,
+    len: LocalId,
+    submemory_size: u32,
+    bounds_mode: BoundsMode,
+) {
+    match bounds_mode {
+        BoundsMode::Mask => {
+            body.local_get(addr);
+            for instr in wrap_address(submemory_size) {
+                body.instr(instr);
+            }
+            body.local_set(addr);
+        }
+        BoundsMode::Trap => {
+            // if addr > submemory_size { unreachable }
+            body.local_get(addr)
+                .i32_const(submemory_size as i32)
+                .binop(BinaryOp::I32GtU)
+                .if_else(
+                    None,
+                    |then| {
+                        then.unreachable();
+                    },
+                    |_| {},
+                );
+        }
+    }
+    // if submemory_size - addr < len { unreachable }
+    body.i32_const(submemory_size as i32)
+        .local_get(addr)
+        .binop(BinaryOp::I32Sub)
+        .local_get(len)
+        .binop(BinaryOp::I32LtU)
+        .if_else(
+            None,
+            |then| {
+                then.unreachable();
+            },
+            |_| {},
+        );
 }
 
-fn rewrite_function(func: &mut LocalFunction, context: &Context) -> anyhow::Result<()> {
+fn rewrite_function(func: &mut LocalFunction, context: &Context) -> InstrCounts {
+    let mut counts = InstrCounts {
+        instrs_before: instr_count(func),
+        size_before: func
+            .original_range
+            .as_ref()
+            .map_or(0, |range| range.end - range.start),
+        ..Default::default()
+    };
     let block_ids: Vec<_> = func.blocks().map(|(block_id, _block)| block_id).collect();
     for block_id in block_ids {
-        rewrite_block(func, block_id, context)?;
+        rewrite_block(func, block_id, context, &mut counts);
     }
-    Ok(())
+    counts.instrs_after = instr_count(func);
+    counts
+}
+
+fn instr_count(func: &LocalFunction) -> u32 {
+    func.blocks()
+        .map(|(_block_id, block)| block.instrs.len() as u32)
+        .sum()
 }
 
 fn rewrite_block(
     func: &mut LocalFunction,
     block_id: InstrSeqId,
     context: &Context,
-) -> anyhow::Result<()> {
-    let block = func.block_mut(block_id);
-    let block_instrs = &mut block.instrs;
-    let mask = context.submemory_size - 1;
+    counts: &mut InstrCounts,
+) {
+    let block_instrs = std::mem::take(&mut func.block_mut(block_id).instrs);
 
-    // TODO need to support more memory instructions
     let mut new_instrs: Vec<(Instr, InstrLocId)> = vec![];
     for (instr, instr_loc_id) in block_instrs.iter() {
         match instr {
             Instr::Load(load) => {
-                use walrus::ir::Value::*;
+                counts.loads += 1;
                 let mut new_load = load.clone();
                 new_load.arg.offset = 0;
-                let bounds_checked_instrs = &[
-                    (
-                        Instr::Const(Const {
-                            value: I32(load.arg.offset as i32),
-                        }),
-                        InstrLocId::default(),
-                    ),
-                    (
-                        Instr::Binop(Binop {
-                            op: BinaryOp::I32Add,
-                        }),
-                        InstrLocId::default(),
-                    ),
-                    (
-                        Instr::Const(Const {
-                            value: I32(mask as i32),
-                        }),
-                        InstrLocId::default(),
-                    ),
-                    (
-                        Instr::Binop(Binop {
-                            op: BinaryOp::I32And,
-                        }),
-                        InstrLocId::default(),
-                    ),
-                    (
-                        Instr::GlobalGet(GlobalGet {
-                            global: context.base_global,
-                        }),
-                        InstrLocId::default(),
-                    ),
-                    (
-                        Instr::Binop(Binop {
-                            op: BinaryOp::I32Add,
-                        }),
-                        InstrLocId::default(),
-                    ),
-                    (Instr::Load(new_load), *instr_loc_id),
-                ];
-                new_instrs.extend(bounds_checked_instrs.iter().cloned());
+                let width = load.kind.width();
+                relocate_address(func, &mut new_instrs, load.arg.offset, width, context);
+                new_instrs.push((Instr::Load(new_load), *instr_loc_id));
             }
             Instr::Store(store) => {
-                use walrus::ir::Value::*;
+                counts.stores += 1;
                 let mut new_store = store.clone();
                 new_store.arg.offset = 0;
-                let local = context.saved_values.get(store.kind)?;
-                let bounds_checked_instrs = &[
-                    (Instr::LocalSet(LocalSet { local }), InstrLocId::default()),
-                    (
-                        Instr::Const(Const {
-                            value: I32(store.arg.offset as i32),
-                        }),
-                        InstrLocId::default(),
-                    ),
-                    (
-                        Instr::Binop(Binop {
-                            op: BinaryOp::I32Add,
-                        }),
-                        InstrLocId::default(),
-                    ),
-                    (
-                        Instr::Const(Const {
-                            value: I32(mask as i32),
-                        }),
-                        InstrLocId::default(),
-                    ),
-                    (
-                        Instr::Binop(Binop {
-                            op: BinaryOp::I32And,
-                        }),
-                        InstrLocId::default(),
-                    ),
-                    (
-                        Instr::GlobalGet(GlobalGet {
-                            global: context.base_global,
-                        }),
-                        InstrLocId::default(),
-                    ),
-                    (
-                        Instr::Binop(Binop {
-                            op: BinaryOp::I32Add,
-                        }),
-                        InstrLocId::default(),
-                    ),
-                    (Instr::LocalGet(LocalGet { local }), InstrLocId::default()),
-                    (Instr::Store(new_store), *instr_loc_id),
-                ];
-                new_instrs.extend(bounds_checked_instrs.iter().cloned());
+                let local = context.saved_values.get(store.kind);
+                let width = store.kind.width();
+                relocate_address_under(
+                    func,
+                    &mut new_instrs,
+                    &[local],
+                    store.arg.offset,
+                    width,
+                    context,
+                );
+                new_instrs.push((Instr::Store(new_store), *instr_loc_id));
+            }
+            Instr::LoadSimd(load) => {
+                let mut new_load = load.clone();
+                new_load.arg.offset = 0;
+                let width = simd_width(load.kind);
+                match load.kind {
+                    // Lane accesses take the vector operand above the address.
+                    LoadSimdKind::V128Load8Lane(_)
+                    | LoadSimdKind::V128Load16Lane(_)
+                    | LoadSimdKind::V128Load32Lane(_)
+                    | LoadSimdKind::V128Load64Lane(_) => {
+                        counts.loads += 1;
+                        let local = context.saved_values.val_v128;
+                        relocate_address_under(
+                            func,
+                            &mut new_instrs,
+                            &[local],
+                            load.arg.offset,
+                            width,
+                            context,
+                        );
+                    }
+                    LoadSimdKind::V128Store8Lane(_)
+                    | LoadSimdKind::V128Store16Lane(_)
+                    | LoadSimdKind::V128Store32Lane(_)
+                    | LoadSimdKind::V128Store64Lane(_) => {
+                        counts.stores += 1;
+                        let local = context.saved_values.val_v128;
+                        relocate_address_under(
+                            func,
+                            &mut new_instrs,
+                            &[local],
+                            load.arg.offset,
+                            width,
+                            context,
+                        );
+                    }
+                    _ => {
+                        counts.loads += 1;
+                        relocate_address(func, &mut new_instrs, load.arg.offset, width, context);
+                    }
+                }
+                new_instrs.push((Instr::LoadSimd(new_load), *instr_loc_id));
             }
             Instr::MemorySize(_) => {
+                counts.memory_sizes += 1;
                 new_instrs.push((
                     Instr::Call(Call {
                         func: context.fake_memory_size,
@@ -353,6 +1354,7 @@ fn rewrite_block(
                 ));
             }
             Instr::MemoryGrow(_) => {
+                counts.memory_grows += 1;
                 new_instrs.push((
                     Instr::Call(Call {
                         func: context.fake_memory_grow,
@@ -360,15 +1362,116 @@ fn rewrite_block(
                     *instr_loc_id,
                 ));
             }
-            Instr::MemoryInit(_)
-            | Instr::MemoryCopy(_)
-            | Instr::MemoryFill(_)
-            | Instr::LoadSimd(_)
-            | Instr::Cmpxchg(_)
-            | Instr::AtomicRmw(_)
-            | Instr::AtomicWait(_)
-            | Instr::AtomicNotify(_) => {
-                anyhow::bail!("unsupported instruction: {:?}", instr);
+            Instr::MemoryFill(_) => {
+                counts.bulk_memory += 1;
+                new_instrs.push((
+                    Instr::Call(Call {
+                        func: context.fake_memory_fill,
+                    }),
+                    *instr_loc_id,
+                ));
+            }
+            Instr::MemoryCopy(_) => {
+                counts.bulk_memory += 1;
+                new_instrs.push((
+                    Instr::Call(Call {
+                        func: context.fake_memory_copy,
+                    }),
+                    *instr_loc_id,
+                ));
+            }
+            Instr::MemoryInit(MemoryInit { data, .. }) => {
+                counts.bulk_memory += 1;
+                // Active segments are rejected by `analyze_module`.
+                let passive_data = &context.passive_data[data];
+                new_instrs.push((
+                    Instr::Call(Call {
+                        func: passive_data.fake_memory_init,
+                    }),
+                    *instr_loc_id,
+                ));
+            }
+            Instr::DataDrop(DataDrop { data }) => {
+                counts.bulk_memory += 1;
+                let passive_data = &context.passive_data[data];
+                new_instrs.push((
+                    Instr::Const(Const {
+                        value: Value::I32(passive_data.segment as i32),
+                    }),
+                    InstrLocId::default(),
+                ));
+                new_instrs.push((
+                    Instr::Call(Call {
+                        func: context.fake_data_drop,
+                    }),
+                    *instr_loc_id,
+                ));
+            }
+            Instr::AtomicRmw(rmw) => {
+                counts.atomics += 1;
+                let mut new_rmw = rmw.clone();
+                new_rmw.arg.offset = 0;
+                let (value, _) = context.saved_values.get_atomic(rmw.width);
+                let width = rmw.width.bytes();
+                relocate_address_under(
+                    func,
+                    &mut new_instrs,
+                    &[value],
+                    rmw.arg.offset,
+                    width,
+                    context,
+                );
+                new_instrs.push((Instr::AtomicRmw(new_rmw), *instr_loc_id));
+            }
+            Instr::Cmpxchg(cmpxchg) => {
+                counts.atomics += 1;
+                let mut new_cmpxchg = cmpxchg.clone();
+                new_cmpxchg.arg.offset = 0;
+                let (expected, replacement) = context.saved_values.get_atomic(cmpxchg.width);
+                relocate_address_under(
+                    func,
+                    &mut new_instrs,
+                    &[expected, replacement],
+                    cmpxchg.arg.offset,
+                    cmpxchg.width.bytes(),
+                    context,
+                );
+                new_instrs.push((Instr::Cmpxchg(new_cmpxchg), *instr_loc_id));
+            }
+            Instr::AtomicWait(wait) => {
+                counts.atomics += 1;
+                let mut new_wait = wait.clone();
+                new_wait.arg.offset = 0;
+                let (expected, width) = if wait.sixty_four {
+                    (context.saved_values.val_i64, 8)
+                } else {
+                    (context.saved_values.val_i32, 4)
+                };
+                let timeout = context.saved_values.val2_i64;
+                relocate_address_under(
+                    func,
+                    &mut new_instrs,
+                    &[expected, timeout],
+                    wait.arg.offset,
+                    width,
+                    context,
+                );
+                new_instrs.push((Instr::AtomicWait(new_wait), *instr_loc_id));
+            }
+            Instr::AtomicNotify(notify) => {
+                counts.atomics += 1;
+                let mut new_notify = notify.clone();
+                new_notify.arg.offset = 0;
+                let count = context.saved_values.val_i32;
+                relocate_address_under(
+                    func,
+                    &mut new_instrs,
+                    &[count],
+                    notify.arg.offset,
+                    4,
+                    context,
+                );
+                new_instrs.push((Instr::AtomicNotify(new_notify), *instr_loc_id));
             }
             _ => {
                 new_instrs.push((instr.clone(), *instr_loc_id));
@@ -376,8 +1479,159 @@ fn rewrite_block(
         }
     }
 
-    block.instrs = new_instrs;
-    Ok(())
+    func.block_mut(block_id).instrs = new_instrs;
+}
+
+/// Replaces the address on top of the stack, displaced by `offset`, with the
+/// corresponding address in the current submemory. `width` is the number of
+/// bytes accessed.
+///
+/// Submemory base addresses and sizes are multiples of the page size, so
+/// wrapping preserves the address modulo the page size and the relocated
+/// address has the same alignment as the original. Misaligned atomic
+/// accesses therefore still trap and aligned ones stay aligned.
+fn relocate_address(
+    func: &mut LocalFunction,
+    new_instrs: &mut Vec<(Instr, InstrLocId)>,
+    offset: u32,
+    width: u32,
+    context: &Context,
+) {
+    let confine_instrs = match context.bounds_mode {
+        BoundsMode::Mask => {
+            let mut instrs = vec![
+                Instr::Const(Const {
+                    value: Value::I32(offset as i32),
+                }),
+                Instr::Binop(Binop {
+                    op: BinaryOp::I32Add,
+                }),
+            ];
+            instrs.extend(wrap_address(context.submemory_size));
+            instrs
+        }
+        BoundsMode::Trap => {
+            let Some(limit) = context
+                .submemory_size
+                .checked_sub(offset)
+                .and_then(|limit| limit.checked_sub(width))
+            else {
+                // The access is out of range for every address.
+                new_instrs.push((Instr::Unreachable(Unreachable {}), InstrLocId::default()));
+                return;
+            };
+            let local = context.saved_values.address;
+            let consequent = func
+                .builder_mut()
+                .dangling_instr_seq(None)
+                .unreachable()
+                .id();
+            let alternative = func.builder_mut().dangling_instr_seq(None).id();
+            vec![
+                // if address > submemory_size - offset - width { unreachable }
+                Instr::LocalTee(LocalTee { local }),
+                Instr::Const(Const {
+                    value: Value::I32(limit as i32),
+                }),
+                Instr::Binop(Binop {
+                    op: BinaryOp::I32GtU,
+                }),
+                Instr::IfElse(IfElse {
+                    consequent,
+                    alternative,
+                }),
+                Instr::LocalGet(LocalGet { local }),
+                Instr::Const(Const {
+                    value: Value::I32(offset as i32),
+                }),
+                Instr::Binop(Binop {
+                    op: BinaryOp::I32Add,
+                }),
+            ]
+        }
+    };
+    new_instrs.extend(
+        confine_instrs
+            .into_iter()
+            .chain([
+                Instr::GlobalGet(GlobalGet {
+                    global: context.base_global,
+                }),
+                Instr::Binop(Binop {
+                    op: BinaryOp::I32Add,
+                }),
+            ])
+            .map(|instr| (instr, InstrLocId::default())),
+    );
+}
+
+/// Returns instructions that wrap the address on top of the stack around within
+/// the submemory. Power-of-two sizes use a mask, other sizes a remainder.
+fn wrap_address(submemory_size: u32) -> [Instr; 2] {
+    if submemory_size.is_power_of_two() {
+        [
+            Instr::Const(Const {
+                value: Value::I32((submemory_size - 1) as i32),
+            }),
+            Instr::Binop(Binop {
+                op: BinaryOp::I32And,
+            }),
+        ]
+    } else {
+        [
+            Instr::Const(Const {
+                value: Value::I32(submemory_size as i32),
+            }),
+            Instr::Binop(Binop {
+                op: BinaryOp::I32RemU,
+            }),
+        ]
+    }
+}
+
+/// Like `relocate_address`, for an address that is followed on the stack by
+/// operands to be saved in `locals`.
+fn relocate_address_under(
+    func: &mut LocalFunction,
+    new_instrs: &mut Vec<(Instr, InstrLocId)>,
+    locals: &[LocalId],
+    offset: u32,
+    width: u32,
+    context: &Context,
+) {
+    for &local in locals.iter().rev() {
+        new_instrs.push((Instr::LocalSet(LocalSet { local }), InstrLocId::default()));
+    }
+    relocate_address(func, new_instrs, offset, width, context);
+    for &local in locals {
+        new_instrs.push((Instr::LocalGet(LocalGet { local }), InstrLocId::default()));
+    }
+}
+
+/// Returns the number of bytes accessed by a SIMD memory instruction.
+fn simd_width(kind: LoadSimdKind) -> u32 {
+    match kind {
+        LoadSimdKind::Splat8 | LoadSimdKind::V128Load8Lane(_) | LoadSimdKind::V128Store8Lane(_) => {
+            1
+        }
+        LoadSimdKind::Splat16
+        | LoadSimdKind::V128Load16Lane(_)
+        | LoadSimdKind::V128Store16Lane(_) => 2,
+        LoadSimdKind::Splat32
+        | LoadSimdKind::V128Load32Zero
+        | LoadSimdKind::V128Load32Lane(_)
+        | LoadSimdKind::V128Store32Lane(_) => 4,
+        LoadSimdKind::Splat64
+        | LoadSimdKind::V128Load8x8S
+        | LoadSimdKind::V128Load8x8U
+        | LoadSimdKind::V128Load16x4S
+        | LoadSimdKind::V128Load16x4U
+        | LoadSimdKind::V128Load32x2S
+        | LoadSimdKind::V128Load32x2U
+        | LoadSimdKind::V128Load64Zero
+        | LoadSimdKind::V128Load64Lane(_)
+        | LoadSimdKind::V128Store64Lane(_) => 8,
+    }
 }
 
 struct SavedValues {
@@ -386,6 +1640,9 @@ struct SavedValues {
     val_i64: LocalId,
     val_f64: LocalId,
     val_v128: LocalId,
+    val2_i32: LocalId,
+    val2_i64: LocalId,
+    address: LocalId,
 }
 
 impl SavedValues {
@@ -396,11 +1653,26 @@ impl SavedValues {
             val_i64: module.locals.add(ValType::I64),
             val_f64: module.locals.add(ValType::F64),
             val_v128: module.locals.add(ValType::V128),
+            val2_i32: module.locals.add(ValType::I32),
+            val2_i64: module.locals.add(ValType::I64),
+            address: module.locals.add(ValType::I32),
         }
     }
 
-    fn get(&self, store_kind: StoreKind) -> anyhow::Result<LocalId> {
-        Ok(match store_kind {
+    /// Returns two locals of the operand type of an atomic operation.
+    fn get_atomic(&self, width: AtomicWidth) -> (LocalId, LocalId) {
+        match width {
+            AtomicWidth::I32 | AtomicWidth::I32_8 | AtomicWidth::I32_16 => {
+                (self.val_i32, self.val2_i32)
+            }
+            AtomicWidth::I64 | AtomicWidth::I64_8 | AtomicWidth::I64_16 | AtomicWidth::I64_32 => {
+                (self.val_i64, self.val2_i64)
+            }
+        }
+    }
+
+    fn get(&self, store_kind: StoreKind) -> LocalId {
+        match store_kind {
             walrus::ir::StoreKind::I32 { .. } => self.val_i32,
             walrus::ir::StoreKind::I32_8 { .. } => self.val_i32,
             walrus::ir::StoreKind::I32_16 { .. } => self.val_i32,
@@ -409,8 +1681,205 @@ impl SavedValues {
             walrus::ir::StoreKind::I64_16 { .. } => self.val_i64,
             walrus::ir::StoreKind::I64_32 { .. } => self.val_i64,
             walrus::ir::StoreKind::F32 => self.val_f32,
-            walrus::ir::StoreKind::F64 { .. } => self.val_f64,
-            walrus::ir::StoreKind::V128 { .. } => self.val_v128,
-        })
+            walrus::ir::StoreKind::F64 => self.val_f64,
+            walrus::ir::StoreKind::V128 => self.val_v128,
+        }
+    }
+}
+
+/// Layout of the per-submemory bookkeeping records in the headroom.
+struct Records {
+    size: u32,
+    passive_data: Vec<DataId>,
+    globals: Vec<SavedGlobal>,
+}
+
+/// A mutable guest global saved at `offset` within each record.
+struct SavedGlobal {
+    id: GlobalId,
+    offset: u32,
+    load_kind: LoadKind,
+    store_kind: StoreKind,
+    align: u32,
+}
+
+impl Records {
+    /// Offset of the next free submemory index within each record.
+    const NEXT_FREE_OFFSET: u32 = 4;
+    /// Offset of the highest allocated page count within each record.
+    const PEAK_PAGES_OFFSET: u32 = 8;
+    /// Offset of the dropped data segment bitmap within each record.
+    const DROPPED_OFFSET: u32 = 12;
+    /// Value of the index global while no submemory is selected, before the
+    /// first `select_submemory` and once the current submemory is removed.
+    /// Not -1, since `records[-1]` is the template record.
+    const NO_SUBMEMORY: i32 = -2;
+
+    fn new(module: &walrus::Module) -> Self {
+        let passive_data: Vec<_> = module
+            .data
+            .iter()
+            .filter(|data| matches!(data.kind, walrus::DataKind::Passive))
+            .map(|data| data.id())
+            .collect();
+        // allocated_pages: i32, next_free: i32, peak_pages: i32, dropped: [i32; N]
+        let mut offset = Self::DROPPED_OFFSET + passive_data.len().div_ceil(32) as u32 * 4;
+        let mut max_align = 4;
+        let mut globals = Vec::new();
+        for global in module.globals.iter().filter(|g| g.mutable) {
+            let (load_kind, store_kind, align) = match global.ty {
+                ValType::I32 => (
+                    LoadKind::I32 { atomic: false },
+                    StoreKind::I32 { atomic: false },
+                    4,
+                ),
+                ValType::I64 => (
+                    LoadKind::I64 { atomic: false },
+                    StoreKind::I64 { atomic: false },
+                    8,
+                ),
+                ValType::F32 => (LoadKind::F32, StoreKind::F32, 4),
+                ValType::F64 => (LoadKind::F64, StoreKind::F64, 8),
+                ValType::V128 => (LoadKind::V128, StoreKind::V128, 16),
+                // Rejected by `analyze_module`.
+                _ => unreachable!(),
+            };
+            offset = offset.next_multiple_of(align);
+            globals.push(SavedGlobal {
+                id: global.id(),
+                offset,
+                load_kind,
+                store_kind,
+                align,
+            });
+            offset += align;
+            max_align = max_align.max(align);
+        }
+        Self {
+            size: offset.next_multiple_of(max_align),
+            passive_data,
+            globals,
+        }
+    }
+
+    /// Traps unless `index` refers to a submemory that has been added, even if
+    /// it has since been removed.
+    fn check_added(body: &mut InstrSeqBuilder, count_global: GlobalId, index: LocalId) {
+        // if index >= count { unreachable }
+        body.local_get(index)
+            .global_get(count_global)
+            .binop(BinaryOp::I32GeU)
+            .if_else(
+                None,
+                |then| {
+                    then.unreachable();
+                },
+                |_| {},
+            );
+    }
+
+    /// Traps unless `index` refers to a submemory that has been added and not
+    /// removed.
+    fn check_live(
+        &self,
+        body: &mut InstrSeqBuilder,
+        memory_id: MemoryId,
+        count_global: GlobalId,
+        index: LocalId,
+    ) {
+        Self::check_added(body, count_global, index);
+        // if records[index].allocated_pages == -1 { unreachable }
+        body.local_get(index)
+            .i32_const(self.size as i32)
+            .binop(BinaryOp::I32Mul)
+            .load(
+                memory_id,
+                LoadKind::I32 { atomic: false },
+                MemArg {
+                    align: 4,
+                    offset: self.size,
+                },
+            )
+            .i32_const(-1)
+            .binop(BinaryOp::I32Eq)
+            .if_else(
+                None,
+                |then| {
+                    then.unreachable();
+                },
+                |_| {},
+            );
+    }
+
+    /// Sets the peak page count of the record at `record + offset` to the
+    /// larger of `peak_pages` and its allocated page count.
+    fn update_peak_pages(
+        &self,
+        body: &mut InstrSeqBuilder,
+        memory_id: MemoryId,
+        record: LocalId,
+        offset: u32,
+        peak_pages: LocalId,
+    ) {
+        let allocated_pages = MemArg { align: 4, offset };
+        body.local_get(record)
+            .local_get(peak_pages)
+            .local_get(record)
+            .load(memory_id, LoadKind::I32 { atomic: false }, allocated_pages)
+            .local_get(peak_pages)
+            .local_get(record)
+            .load(memory_id, LoadKind::I32 { atomic: false }, allocated_pages)
+            .binop(BinaryOp::I32GtU)
+            .select(None)
+            .store(
+                memory_id,
+                StoreKind::I32 { atomic: false },
+                MemArg {
+                    align: 4,
+                    offset: offset + Self::PEAK_PAGES_OFFSET,
+                },
+            );
+    }
+
+    /// Stores every saved global into the record at `record + offset`.
+    fn save_globals(
+        &self,
+        body: &mut InstrSeqBuilder,
+        memory_id: MemoryId,
+        record: LocalId,
+        offset: u32,
+    ) {
+        for global in &self.globals {
+            body.local_get(record).global_get(global.id).store(
+                memory_id,
+                global.store_kind,
+                MemArg {
+                    align: global.align,
+                    offset: offset + global.offset,
+                },
+            );
+        }
+    }
+
+    /// Loads every saved global from the record at `record + offset`.
+    fn restore_globals(
+        &self,
+        body: &mut InstrSeqBuilder,
+        memory_id: MemoryId,
+        record: LocalId,
+        offset: u32,
+    ) {
+        for global in &self.globals {
+            body.local_get(record)
+                .load(
+                    memory_id,
+                    global.load_kind,
+                    MemArg {
+                        align: global.align,
+                        offset: offset + global.offset,
+                    },
+                )
+                .global_set(global.id);
+        }
     }
 }
@@ -0,0 +1,227 @@
+use clap::{Parser, ValueEnum};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use wasm_submemory::{BoundsMode, Metadata, RewriteOptions, RewriteStats, WASM_PAGE_SIZE};
+
+/// Rewrites a wasm module so that it can host many independent copies of its
+/// memory ("submemories") in a single linear memory.
+#[derive(Parser, Debug)]
+#[clap(version)]
+struct Arguments {
+    /// Input module, in binary or text format.
+    input: PathBuf,
+    /// Where to write the rewritten module. A `.wat` extension selects the
+    /// text format.
+    #[clap(short, long, required_unless_present_any = ["check", "stats"])]
+    output: Option<PathBuf>,
+    /// Size of each submemory in bytes, optionally with a K, M or G suffix.
+    #[clap(short, long, default_value = "1M", value_parser = parse_size)]
+    submemory_size: u32,
+    /// How memory accesses are confined to the current submemory.
+    #[clap(short, long, value_enum, default_value_t = Bounds::Mask)]
+    bounds_mode: Bounds,
+    /// Size of the bookkeeping area in bytes, optionally with a K, M or G
+    /// suffix. Defaults to room for --max-submemories.
+    #[clap(long, value_parser = parse_size)]
+    headroom_size: Option<u32>,
+    /// Maximum number of submemories.
+    #[clap(long)]
+    max_submemories: Option<u32>,
+    /// Prefix for the names of the injected exports.
+    #[clap(long, default_value = "")]
+    export_prefix: String,
+    /// Also export the internal base address, index and count globals.
+    #[clap(long)]
+    export_internals: bool,
+    /// Write the text format regardless of the output file extension.
+    #[clap(long)]
+    wat: bool,
+    /// Only report whether the module can be rewritten.
+    #[clap(long)]
+    check: bool,
+    /// Print what was rewritten.
+    #[clap(long)]
+    stats: bool,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Bounds {
+    Mask,
+    Trap,
+}
+
+impl From<Bounds> for BoundsMode {
+    fn from(bounds: Bounds) -> Self {
+        match bounds {
+            Bounds::Mask => BoundsMode::Mask,
+            Bounds::Trap => BoundsMode::Trap,
+        }
+    }
+}
+
+fn parse_size(size: &str) -> Result<u32, String> {
+    let (digits, multiplier) = match size.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&size[..i], 1 << 10),
+        Some((i, 'm' | 'M')) => (&size[..i], 1 << 20),
+        Some((i, 'g' | 'G')) => (&size[..i], 1 << 30),
+        _ => (size, 1),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .and_then(|n| u32::try_from(n).ok())
+        .ok_or_else(|| format!("invalid size: {}", size))
+}
+
+fn main() -> ExitCode {
+    env_logger::init();
+    let args = Arguments::parse();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{}: {:#}", args.input.display(), err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: &Arguments) -> anyhow::Result<()> {
+    let input = std::fs::read(&args.input)?;
+    let wasm = wat::parse_bytes(&input)?;
+
+    let mut options = RewriteOptions::new(args.submemory_size)
+        .bounds_mode(args.bounds_mode.into())
+        .export_prefix(args.export_prefix.clone())
+        .export_internals(args.export_internals);
+    if let Some(headroom_size) = args.headroom_size {
+        options = options.headroom_size(headroom_size);
+    }
+    if let Some(max_submemories) = args.max_submemories {
+        options = options.max_submemories(max_submemories);
+    }
+    if args.check {
+        let report = wasm_submemory::analyze(&wasm)?;
+        for blocker in report.blockers() {
+            println!("{}: {}", args.input.display(), blocker);
+        }
+        if !report.is_compatible() {
+            anyhow::bail!("incompatible ({} problems)", report.blockers().len());
+        }
+    }
+    let (output, metadata, stats) = wasm_submemory::rewrite_with_stats(&wasm, &options)?;
+
+    if args.check {
+        println!("{}: compatible", args.input.display());
+    }
+    if args.stats {
+        print_stats(&metadata, &stats);
+    }
+    if args.check {
+        return Ok(());
+    }
+    if let Some(path) = &args.output {
+        if args.wat || is_wat(path) {
+            std::fs::write(path, wasmprinter::print_bytes(&output)?)?;
+        } else {
+            std::fs::write(path, output)?;
+        }
+    }
+    Ok(())
+}
+
+fn is_wat(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "wat")
+}
+
+fn print_stats(metadata: &Metadata, stats: &RewriteStats) {
+    let layout = metadata.layout();
+    let totals = stats.totals();
+    println!("input size:      {} bytes", stats.input_size());
+    println!("output size:     {} bytes", stats.output_size());
+    println!("input code:      {} bytes", stats.input_code_size());
+    println!("output code:     {} bytes", stats.output_code_size());
+    println!("functions:       {}", stats.functions().len());
+    println!("loads:           {}", totals.loads());
+    println!("stores:          {}", totals.stores());
+    println!("atomics:         {}", totals.atomics());
+    println!("memory.size:     {}", totals.memory_sizes());
+    println!("memory.grow:     {}", totals.memory_grows());
+    println!("bulk memory:     {}", totals.bulk_memory());
+    println!(
+        "instructions:    {} -> {}",
+        totals.instrs_before(),
+        totals.instrs_after()
+    );
+    println!(
+        "guest code:      {} -> {} bytes",
+        totals.size_before(),
+        totals.size_after()
+    );
+    println!(
+        "submemory size:  {} pages",
+        layout.submemory_size() / WASM_PAGE_SIZE
+    );
+    println!("initial pages:   {}", layout.initial_pages());
+    println!(
+        "headroom size:   {} pages",
+        layout.headroom_size() / WASM_PAGE_SIZE
+    );
+    println!("max submemories: {}", metadata.max_submemories());
+    println!("bounds mode:     {:?}", metadata.bounds_mode());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(input: &Path) -> anyhow::Result<()> {
+        let args = Arguments::try_parse_from([
+            "wasm-submemory".as_ref(),
+            "--check".as_ref(),
+            input.as_os_str(),
+        ])?;
+        run(&args)
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("64k"), Ok(64 << 10));
+        assert_eq!(parse_size("64K"), Ok(64 << 10));
+        assert_eq!(parse_size("1m"), Ok(1 << 20));
+        assert_eq!(parse_size("16M"), Ok(16 << 20));
+        assert_eq!(parse_size("2g"), Ok(2 << 30));
+        assert_eq!(parse_size("3G"), Ok(3 << 30));
+    }
+
+    #[test]
+    fn invalid_sizes() {
+        for size in ["", "k", "M", "-1", "1.5M", "1T", "4G", "4294967296", "1 M"] {
+            assert_eq!(
+                parse_size(size),
+                Err(format!("invalid size: {}", size)),
+                "{:?}",
+                size
+            );
+        }
+    }
+
+    #[test]
+    fn check_compatible() -> anyhow::Result<()> {
+        let input = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/wasm/c/data.wasm");
+        check(&input)
+    }
+
+    #[test]
+    fn check_incompatible() -> anyhow::Result<()> {
+        let input =
+            std::env::temp_dir().join(format!("wasm-submemory-check-{}.wat", std::process::id()));
+        std::fs::write(&input, "(module)")?;
+        let result = check(&input);
+        std::fs::remove_file(&input)?;
+        let err = result.expect_err("a module without a memory is incompatible");
+        assert_eq!(err.to_string(), "incompatible (1 problems)");
+        Ok(())
+    }
+}
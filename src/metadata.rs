@@ -0,0 +1,176 @@
+// A custom section recording how a module was rewritten, so hosts can
+// configure themselves from the rewritten module alone.
+//
+// The section contains little-endian fields:
+// version: u32
+// submemory_size: u32
+// headroom_size: u32
+// initial_pages: u32
+// max_submemories: u32
+// bounds_mode: u8 (0 = mask, 1 = trap)
+// export_internals: u8 (0 = no, 1 = yes)
+// export_prefix_len: u32
+// export_prefix: [u8; export_prefix_len] (UTF-8)
+
+use crate::{binary, BoundsMode, Error, Layout, Result};
+
+/// Name of the custom section holding the metadata.
+pub const METADATA_SECTION: &str = "wasm-submemory";
+
+/// Description of a rewritten module, stored in its `METADATA_SECTION`
+/// custom section.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Metadata {
+    layout: Layout,
+    max_submemories: u32,
+    bounds_mode: BoundsMode,
+    export_internals: bool,
+    export_prefix: String,
+}
+
+impl Metadata {
+    /// Version of the section format written by this crate.
+    pub const VERSION: u32 = 2;
+
+    pub(crate) fn new(
+        layout: Layout,
+        max_submemories: u32,
+        bounds_mode: BoundsMode,
+        export_internals: bool,
+        export_prefix: String,
+    ) -> Self {
+        Self {
+            layout,
+            max_submemories,
+            bounds_mode,
+            export_internals,
+            export_prefix,
+        }
+    }
+
+    /// Reads the metadata of a rewritten module. Fails if the module was not
+    /// rewritten or was rewritten by an incompatible version of this crate.
+    pub fn from_wasm(wasm: &[u8]) -> Result<Self> {
+        if !binary::is_wasm(wasm) {
+            return Err(Error::Parse(anyhow::anyhow!("missing wasm magic number")));
+        }
+        let Some(data) = binary::custom_section(wasm, METADATA_SECTION) else {
+            return Err(Error::MissingMetadata);
+        };
+        Self::decode(data)
+    }
+
+    pub fn layout(&self) -> &Layout {
+        &self.layout
+    }
+
+    pub fn max_submemories(&self) -> u32 {
+        self.max_submemories
+    }
+
+    pub fn bounds_mode(&self) -> BoundsMode {
+        self.bounds_mode
+    }
+
+    /// Returns whether the module exports the `__submemory_base`,
+    /// `__submemory_index` and `__submemory_count` globals, see
+    /// `RewriteOptions::export_internals`.
+    pub fn export_internals(&self) -> bool {
+        self.export_internals
+    }
+
+    pub fn export_prefix(&self) -> &str {
+        &self.export_prefix
+    }
+
+    /// Returns the name of the injected export `name`, e.g. `add_submemory`.
+    pub fn export_name(&self, name: &str) -> String {
+        format!("{}{}", self.export_prefix, name)
+    }
+
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        for value in [
+            Self::VERSION,
+            self.layout.submemory_size(),
+            self.layout.headroom_size(),
+            self.layout.initial_pages(),
+            self.max_submemories,
+        ] {
+            data.extend(value.to_le_bytes());
+        }
+        data.push(match self.bounds_mode {
+            BoundsMode::Mask => 0,
+            BoundsMode::Trap => 1,
+        });
+        data.push(self.export_internals as u8);
+        data.extend((self.export_prefix.len() as u32).to_le_bytes());
+        data.extend(self.export_prefix.as_bytes());
+        data
+    }
+
+    fn decode(data: &[u8]) -> Result<Self> {
+        let mut reader = Reader { data };
+        let version = reader.u32()?;
+        if version != Self::VERSION {
+            return Err(Error::UnsupportedMetadataVersion(version));
+        }
+        let submemory_size = reader.u32()?;
+        let headroom_size = reader.u32()?;
+        let initial_pages = reader.u32()?;
+        let max_submemories = reader.u32()?;
+        let bounds_mode = match reader.bytes(1)?[0] {
+            0 => BoundsMode::Mask,
+            1 => BoundsMode::Trap,
+            mode => {
+                return Err(Error::InvalidMetadata(format!(
+                    "invalid bounds mode {}",
+                    mode
+                )))
+            }
+        };
+        let export_internals = match reader.bytes(1)?[0] {
+            0 => false,
+            1 => true,
+            flag => {
+                return Err(Error::InvalidMetadata(format!(
+                    "invalid export_internals flag {}",
+                    flag
+                )))
+            }
+        };
+        let len = reader.u32()? as usize;
+        let export_prefix = String::from_utf8(reader.bytes(len)?.to_vec())
+            .map_err(|err| Error::InvalidMetadata(format!("invalid export prefix: {}", err)))?;
+        if !reader.data.is_empty() {
+            return Err(Error::InvalidMetadata("trailing bytes".to_string()));
+        }
+        Ok(Self {
+            layout: Layout::new(submemory_size, headroom_size, initial_pages)?,
+            max_submemories,
+            bounds_mode,
+            export_internals,
+            export_prefix,
+        })
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.data.len() < len {
+            return Err(Error::InvalidMetadata("truncated".to_string()));
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
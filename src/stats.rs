@@ -0,0 +1,236 @@
+// Statistics on what a rewrite instrumented and how much it grew the module,
+// for tracking the overhead of rewriting a guest over time.
+
+use crate::{binary, METADATA_SECTION};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use walrus::{CodeTransform, CustomSection, FunctionId, IdsToIndices};
+
+/// Counts of the instructions rewritten in one or more functions, and the
+/// size of their code.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InstrCounts {
+    pub(crate) loads: u32,
+    pub(crate) stores: u32,
+    pub(crate) atomics: u32,
+    pub(crate) memory_sizes: u32,
+    pub(crate) memory_grows: u32,
+    pub(crate) bulk_memory: u32,
+    pub(crate) instrs_before: u32,
+    pub(crate) instrs_after: u32,
+    pub(crate) size_before: usize,
+    pub(crate) size_after: usize,
+}
+
+impl InstrCounts {
+    /// Returns the number of loads, including SIMD loads, relocated into the
+    /// current submemory.
+    pub fn loads(&self) -> u32 {
+        self.loads
+    }
+
+    /// Returns the number of stores, including SIMD lane stores, relocated
+    /// into the current submemory.
+    pub fn stores(&self) -> u32 {
+        self.stores
+    }
+
+    /// Returns the number of atomic read-modify-write, compare-exchange, wait
+    /// and notify instructions relocated into the current submemory.
+    pub fn atomics(&self) -> u32 {
+        self.atomics
+    }
+
+    /// Returns the number of `memory.size` instructions redirected to the
+    /// current submemory.
+    pub fn memory_sizes(&self) -> u32 {
+        self.memory_sizes
+    }
+
+    /// Returns the number of `memory.grow` instructions redirected to the
+    /// current submemory.
+    pub fn memory_grows(&self) -> u32 {
+        self.memory_grows
+    }
+
+    /// Returns the number of `memory.fill`, `memory.copy`, `memory.init` and
+    /// `data.drop` instructions redirected to the current submemory.
+    pub fn bulk_memory(&self) -> u32 {
+        self.bulk_memory
+    }
+
+    /// Returns the number of instructions before rewriting.
+    pub fn instrs_before(&self) -> u32 {
+        self.instrs_before
+    }
+
+    /// Returns the number of instructions after rewriting.
+    pub fn instrs_after(&self) -> u32 {
+        self.instrs_after
+    }
+
+    /// Returns the size of the code before rewriting, in bytes. A function's
+    /// size is that of its body in the code section, including the body's
+    /// size prefix.
+    pub fn size_before(&self) -> usize {
+        self.size_before
+    }
+
+    /// Returns the size of the code after rewriting, in bytes.
+    pub fn size_after(&self) -> usize {
+        self.size_after
+    }
+
+    fn add(&mut self, other: &Self) {
+        self.loads += other.loads;
+        self.stores += other.stores;
+        self.atomics += other.atomics;
+        self.memory_sizes += other.memory_sizes;
+        self.memory_grows += other.memory_grows;
+        self.bulk_memory += other.bulk_memory;
+        self.instrs_before += other.instrs_before;
+        self.instrs_after += other.instrs_after;
+        self.size_before += other.size_before;
+        self.size_after += other.size_after;
+    }
+}
+
+/// Instructions rewritten in a single guest function.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FunctionStats {
+    id: FunctionId,
+    name: String,
+    counts: InstrCounts,
+}
+
+impl FunctionStats {
+    /// Returns the function's name, or `func<index>` if it has none.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn counts(&self) -> &InstrCounts {
+        &self.counts
+    }
+}
+
+/// What `rewrite_with_stats` instrumented, per guest function and in total,
+/// and the module's size before and after rewriting.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RewriteStats {
+    functions: Vec<FunctionStats>,
+    totals: InstrCounts,
+    input_size: usize,
+    output_size: usize,
+    input_code_size: usize,
+    output_code_size: usize,
+}
+
+impl RewriteStats {
+    pub(crate) fn add_function(&mut self, id: FunctionId, name: String, counts: InstrCounts) {
+        self.totals.add(&counts);
+        self.functions.push(FunctionStats { id, name, counts });
+    }
+
+    /// Records the size of each function in the rewritten module.
+    pub(crate) fn set_function_sizes(&mut self, sizes: &HashMap<FunctionId, usize>) {
+        for function in &mut self.functions {
+            let size = sizes.get(&function.id).copied().unwrap_or(0);
+            function.counts.size_after = size;
+            self.totals.size_after += size;
+        }
+    }
+
+    pub(crate) fn set_sizes(&mut self, input: &[u8], output: &[u8]) {
+        self.input_size = input.len();
+        self.output_size = output.len();
+        self.input_code_size = code_section_size(input);
+        self.output_code_size = code_section_size(output);
+    }
+
+    /// Returns the statistics of every rewritten guest function. Functions
+    /// injected by the rewrite are not included.
+    pub fn functions(&self) -> &[FunctionStats] {
+        &self.functions
+    }
+
+    /// Returns the counts summed over every rewritten guest function.
+    pub fn totals(&self) -> &InstrCounts {
+        &self.totals
+    }
+
+    /// Returns the size of the module before rewriting, in bytes.
+    pub fn input_size(&self) -> usize {
+        self.input_size
+    }
+
+    /// Returns the size of the rewritten module, in bytes.
+    pub fn output_size(&self) -> usize {
+        self.output_size
+    }
+
+    /// Returns the size of the code section before rewriting, in bytes.
+    pub fn input_code_size(&self) -> usize {
+        self.input_code_size
+    }
+
+    /// Returns the size of the rewritten code section, in bytes. Unlike
+    /// `totals().size_after()`, this includes the injected functions.
+    pub fn output_code_size(&self) -> usize {
+        self.output_code_size
+    }
+}
+
+/// Returns the size of the contents of a module's code section, or 0 if it
+/// has none.
+fn code_section_size(wasm: &[u8]) -> usize {
+    const CODE_SECTION_ID: u8 = 10;
+
+    binary::sections(wasm)
+        .find(|&(id, _)| id == CODE_SECTION_ID)
+        .map_or(0, |(_, contents)| contents.len())
+}
+
+/// A custom section with fixed contents that also records the size of every
+/// function in the emitted module, which walrus only reveals to custom
+/// sections. The rewrite emits the metadata section this way.
+#[derive(Debug)]
+pub(crate) struct SizingSection {
+    data: Vec<u8>,
+    function_sizes: Arc<Mutex<HashMap<FunctionId, usize>>>,
+}
+
+impl SizingSection {
+    pub(crate) fn new(data: Vec<u8>) -> Self {
+        Self {
+            data,
+            function_sizes: Arc::default(),
+        }
+    }
+
+    /// Returns the size of each function's body in the emitted module,
+    /// including its size prefix. Filled in when the module is emitted with
+    /// `preserve_code_transform` set. walrus drops custom sections while
+    /// emitting, so this must be called before adding the section.
+    pub(crate) fn function_sizes(&self) -> Arc<Mutex<HashMap<FunctionId, usize>>> {
+        self.function_sizes.clone()
+    }
+}
+
+impl CustomSection for SizingSection {
+    fn name(&self) -> &str {
+        METADATA_SECTION
+    }
+
+    fn data(&self, _ids_to_indices: &IdsToIndices) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.data)
+    }
+
+    fn apply_code_transform(&mut self, transform: &CodeTransform) {
+        let mut function_sizes = self.function_sizes.lock().unwrap();
+        for (id, range) in &transform.function_ranges {
+            function_sizes.insert(*id, range.end - range.start);
+        }
+    }
+}
@@ -2,15 +2,26 @@
 cd $(realpath $(dirname $0))
 mkdir -p wasm/rust wasm/c wasm/zig
 
+# Only the bulk_memory fixtures are built with the bulk memory proposal, so
+# the other fixtures keep exercising the rewrite of plain loads and stores.
+# Newer toolchains enable it by default, so it is disabled explicitly.
 for SRC in rust/*.rs; do
         DST=wasm/"${SRC%.rs}.wasm"
-        rustc --crate-name testcrate --edition=2021 --crate-type=cdylib -o "$DST" \
+        FLAGS=(-C target-feature=-bulk-memory)
+        if [[ "$SRC" == */bulk_memory.rs ]]; then
+                FLAGS=(-C target-feature=+bulk-memory)
+        fi
+        rustc "${FLAGS[@]}" --crate-name testcrate --edition=2021 --crate-type=cdylib -o "$DST" \
           --target=wasm32-unknown-unknown -C opt-level=s -C link-arg=-zstack-size=16384 "$SRC"
 done
 
 for SRC in c/*.c; do
         DST=wasm/"${SRC%.c}.wasm"
-        clang --target=wasm32-unknown-unknown -Oz \
+        FLAGS=(-mno-bulk-memory)
+        if [[ "$SRC" == */bulk_memory.c ]]; then
+                FLAGS=(-mbulk-memory)
+        fi
+        clang "${FLAGS[@]}" --target=wasm32-unknown-unknown -Oz \
                 -nostdlib \
                 -Wl,--export-all \
                 -Wl,--no-entry \
@@ -19,7 +30,11 @@ done
 
 for SRC in zig/*.zig; do
         DST=wasm/"${SRC%.zig}.wasm"
-        zig build-lib -O ReleaseSmall -target wasm32-freestanding --export=entry -dynamic \
+        FLAGS=(-mcpu=generic-bulk_memory)
+        if [[ "$SRC" == */bulk_memory.zig ]]; then
+                FLAGS=(-mcpu=generic+bulk_memory)
+        fi
+        zig build-lib "${FLAGS[@]}" -O ReleaseSmall -target wasm32-freestanding --export=entry -dynamic \
                 --export-memory --initial-memory=65536 --stack 16384 \
                 "$SRC"
         mv *.wasm "$DST"
@@ -0,0 +1,19 @@
+#include <stdint.h>
+
+static uint8_t buf[128];
+
+static uint32_t clamp(uint32_t len) {
+    return len < 64 ? len : 64;
+}
+
+void fill(int32_t value, uint32_t len) {
+    __builtin_memset(buf, value, clamp(len));
+}
+
+void copy(uint32_t len) {
+    __builtin_memcpy(buf + 64, buf, clamp(len));
+}
+
+int32_t entry(uint32_t index) {
+    return buf[index % 128];
+}
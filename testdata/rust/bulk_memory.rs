@@ -0,0 +1,20 @@
+static mut BUF: [u8; 128] = [0; 128];
+
+fn buf() -> &'static mut [u8; 128] {
+    unsafe { &mut *std::ptr::addr_of_mut!(BUF) }
+}
+
+#[no_mangle]
+pub fn fill(value: i32, len: usize) {
+    buf()[..len.min(64)].fill(value as u8);
+}
+
+#[no_mangle]
+pub fn copy(len: usize) {
+    buf().copy_within(..len.min(64), 64);
+}
+
+#[no_mangle]
+pub fn entry(index: usize) -> i32 {
+    buf()[index % 128] as i32
+}
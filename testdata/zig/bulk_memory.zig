@@ -0,0 +1,26 @@
+comptime {
+    @export(fill, .{ .name = "fill", .linkage = .Strong });
+}
+
+comptime {
+    @export(copy, .{ .name = "copy", .linkage = .Strong });
+}
+
+comptime {
+    @export(entry, .{ .name = "entry", .linkage = .Strong });
+}
+
+var buf: [128]u8 = [_]u8{0} ** 128;
+
+pub fn fill(value: i32, len: u32) callconv(.C) void {
+    @memset(buf[0..@min(len, 64)], @truncate(@as(u32, @bitCast(value))));
+}
+
+pub fn copy(len: u32) callconv(.C) void {
+    const n = @min(len, 64);
+    @memcpy(buf[64 .. 64 + n], buf[0..n]);
+}
+
+pub fn entry(index: u32) callconv(.C) i32 {
+    return buf[index % 128];
+}
@@ -0,0 +1,139 @@
+mod common;
+
+use crate::common::*;
+use testresult::TestResult;
+use wasm_submemory::{Blocker, Error};
+
+#[test]
+fn compatible() -> TestResult {
+    let wasm = parse_wat(
+        r#"
+(module
+  (func $entry (result i32)
+    i32.const 0
+    i32.load)
+  (global $counter (mut i32) (i32.const 0))
+  (memory (;0;) 3)
+  (export "memory" (memory 0))
+  (export "entry" (func $entry)))
+"#,
+    )?;
+    let report = wasm_submemory::analyze(&wasm)?;
+    assert!(report.is_compatible());
+    assert_eq!(report.blockers(), []);
+    assert_eq!(report.min_submemory_size(), WASM_PAGE_SIZE * 3);
+
+    Ok(())
+}
+
+#[test]
+fn every_blocker() -> TestResult {
+    let wasm = parse_wat(
+        r#"
+(module
+  (import "env" "offset" (global $offset i32))
+  (func $init
+    i32.const 0
+    i32.const 0
+    i32.const 4
+    memory.init $active)
+  (func $drop
+    nop
+    data.drop $active)
+  (func
+    i32.const 0
+    i32.const 0
+    i32.const 4
+    memory.init $active)
+  (global $ref (mut externref) (ref.null extern))
+  (memory (;0;) 1)
+  (memory (;1;) 1)
+  (data $active (i32.const 0) "abcd")
+  (data $relative (global.get $offset) "efgh")
+  (export "memory" (memory 0)))
+"#,
+    )?;
+    let report = wasm_submemory::analyze(&wasm)?;
+    assert!(!report.is_compatible());
+    let blockers = report.blockers();
+    assert_eq!(blockers.len(), 6, "{:#?}", blockers);
+    assert_eq!(blockers[0], Blocker::MultipleMemories { count: 2 });
+    assert_eq!(
+        blockers[1],
+        Blocker::RelativeDataSegment {
+            segment: "relative".to_string()
+        }
+    );
+    assert_eq!(
+        blockers[2],
+        Blocker::UnsupportedGlobal {
+            global: "ref".to_string(),
+            ty: "Externref".to_string()
+        }
+    );
+    let instructions: Vec<_> = blockers[3..]
+        .iter()
+        .map(|blocker| match blocker {
+            Blocker::UnsupportedInstruction {
+                function,
+                offset,
+                instruction,
+            } => {
+                assert!(offset.is_some());
+                format!("{}: {}", function, instruction)
+            }
+            _ => panic!("unexpected blocker {:?}", blocker),
+        })
+        .collect();
+    assert!(instructions.contains(&"init: memory.init of active data segment".to_string()));
+    assert!(instructions.contains(&"drop: data.drop of active data segment".to_string()));
+    assert!(instructions.contains(&"func2: memory.init of active data segment".to_string()));
+
+    // rewrite reports the first blocker.
+    let ret = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE);
+    assert!(matches!(
+        ret,
+        Err(Error::Incompatible(Blocker::MultipleMemories { count: 2 }))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn no_memory() -> TestResult {
+    let wasm = parse_wat("(module)")?;
+    let report = wasm_submemory::analyze(&wasm)?;
+    assert_eq!(report.blockers(), [Blocker::NoMemory]);
+    assert_eq!(report.blockers()[0].to_string(), "wasm file has no memory");
+
+    let ret = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE);
+    assert!(matches!(ret, Err(Error::Incompatible(Blocker::NoMemory))));
+    let ret = wasm_submemory::rewrite(b"not wasm", SUBMEMORY_SIZE);
+    assert!(matches!(ret, Err(Error::Parse(_))));
+
+    Ok(())
+}
+
+#[test]
+fn memory64() -> TestResult {
+    for wat in [
+        r#"(module (memory i64 1))"#,
+        r#"(module
+  (import "env" "f" (func (param i32)))
+  (import "env" "g" (global i32))
+  (import "env" "memory" (memory i64 1 2)))"#,
+    ] {
+        let wasm = parse_wat(wat)?;
+        let report = wasm_submemory::analyze(&wasm)?;
+        assert_eq!(report.blockers(), [Blocker::Memory64]);
+
+        let ret = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE);
+        assert!(matches!(ret, Err(Error::Incompatible(Blocker::Memory64))));
+    }
+
+    // 32-bit imported memories are still rewritten.
+    let wasm = parse_wat(r#"(module (import "env" "memory" (memory 1)))"#)?;
+    assert!(wasm_submemory::analyze(&wasm)?.is_compatible());
+
+    Ok(())
+}
@@ -0,0 +1,159 @@
+mod common;
+
+use crate::common::*;
+use testresult::TestResult;
+use wasmer::Value;
+
+#[test]
+fn rmw_counter() -> TestResult {
+    let wasm = parse_wat(
+        r#"
+(module
+  (type (;0;) (func (result i32)))
+  (func $entry (type 0) (result i32)
+    i32.const 0
+    i32.const 1
+    i32.atomic.rmw.add offset=64
+    i32.const 0
+    i64.const 2
+    i64.atomic.rmw.add offset=72
+    i32.wrap_i64
+    i32.add
+    i32.const 0
+    i32.atomic.load offset=64
+    i32.add)
+  (memory (;0;) 1 1 shared)
+  (export "memory" (memory 0))
+  (export "entry" (func $entry)))
+            "#,
+    )?;
+
+    let wasm = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE)?;
+    let mut vm = VM::new(&wasm)?;
+    for i in 0..10 {
+        assert_eq!(vm.add_submemory()?.0, i);
+    }
+    for i in 1..=10 {
+        for j in 0..10 {
+            vm.select_submemory(j)?;
+            let ret = vm.call("entry", &[])?;
+            assert_eq!(*ret, [Value::I32(4 * i - 3)], "{} {}", i, j);
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn cmpxchg_counter() -> TestResult {
+    let wasm = parse_wat(
+        r#"
+(module
+  (type (;0;) (func (result i64)))
+  (func $entry (type 0) (result i64)
+    (local i64)
+    i32.const 0
+    i32.const 0
+    i64.atomic.load offset=64
+    local.tee 0
+    local.get 0
+    i64.const 1
+    i64.add
+    i64.atomic.rmw.cmpxchg offset=64
+    i64.const 1
+    i64.add)
+  (memory (;0;) 1)
+  (export "memory" (memory 0))
+  (export "entry" (func $entry)))
+            "#,
+    )?;
+
+    let wasm = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE)?;
+    let mut vm = VM::new(&wasm)?;
+    for i in 0..10 {
+        assert_eq!(vm.add_submemory()?.0, i);
+    }
+    for i in 1..=10 {
+        for j in 0..10 {
+            vm.select_submemory(j)?;
+            let ret = vm.call("entry", &[])?;
+            assert_eq!(*ret, [Value::I64(i as i64)], "{} {}", i, j);
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn wait_notify() -> TestResult {
+    let wasm = parse_wat(
+        r#"
+(module
+  (type (;0;) (func (result i32)))
+  (func $wait (type 0) (result i32)
+    i32.const 0
+    i32.const 1
+    i64.const 0
+    memory.atomic.wait32 offset=64)
+  (func $notify (type 0) (result i32)
+    i32.const 0
+    i32.const 1
+    memory.atomic.notify offset=64)
+  (func $inc (type 0) (result i32)
+    i32.const 0
+    i32.const 1
+    i32.atomic.rmw.add offset=64)
+  (memory (;0;) 1 1 shared)
+  (export "memory" (memory 0))
+  (export "wait" (func $wait))
+  (export "notify" (func $notify))
+  (export "inc" (func $inc)))
+            "#,
+    )?;
+
+    let wasm = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE)?;
+    let mut vm = VM::new(&wasm)?;
+    for i in 0..2 {
+        assert_eq!(vm.add_submemory()?.0, i);
+    }
+
+    vm.select_submemory(0)?;
+    vm.call("inc", &[])?;
+    // "timed-out" in submemory 0, "not-equal" in submemory 1.
+    assert_eq!(*vm.call("wait", &[])?, [Value::I32(2)]);
+    vm.select_submemory(1)?;
+    assert_eq!(*vm.call("wait", &[])?, [Value::I32(1)]);
+    assert_eq!(*vm.call("notify", &[])?, [Value::I32(0)]);
+
+    Ok(())
+}
+
+#[test]
+fn misaligned() -> TestResult {
+    let wasm = parse_wat(
+        r#"
+(module
+  (type (;0;) (func (param i32) (result i32)))
+  (func $entry (type 0) (param i32) (result i32)
+    local.get 0
+    i32.atomic.load offset=64)
+  (memory (;0;) 1)
+  (export "memory" (memory 0))
+  (export "entry" (func $entry)))
+            "#,
+    )?;
+
+    let wasm = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE)?;
+    let mut vm = VM::new(&wasm)?;
+    for i in 0..2 {
+        assert_eq!(vm.add_submemory()?.0, i);
+    }
+    for i in 0..2 {
+        vm.select_submemory(i)?;
+        vm.call("entry", &[Value::I32(4)])?;
+        vm.call("entry", &[Value::I32(SUBMEMORY_SIZE as i32 * 3 + 4)])?;
+        assert!(vm.call("entry", &[Value::I32(2)]).is_err());
+    }
+
+    Ok(())
+}
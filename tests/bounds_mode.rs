@@ -0,0 +1,165 @@
+mod common;
+
+use crate::common::*;
+use testresult::TestResult;
+use wasm_submemory::{BoundsMode, RewriteOptions};
+use wasmer::Value;
+
+struct Testcase<'a> {
+    name: &'a str,
+    wasm: &'a [u8],
+}
+
+const WAT: &str = r#"
+(module
+  (type (;0;) (func (param i32) (result i32)))
+  (type (;1;) (func (param i32 i32)))
+  (func $load (type 0) (param i32) (result i32)
+    local.get 0
+    i32.load offset=64)
+  (func $store (type 1) (param i32 i32)
+    local.get 0
+    local.get 1
+    i32.store offset=64)
+  (func $fill (type 1) (param i32 i32)
+    local.get 0
+    i32.const 0
+    local.get 1
+    memory.fill)
+  (memory (;0;) 1)
+  (export "memory" (memory 0))
+  (export "load" (func $load))
+  (export "store" (func $store))
+  (export "fill" (func $fill)))
+"#;
+
+#[test]
+fn mask() -> TestResult {
+    let wasm = parse_wat(WAT)?;
+    let wasm = wasm_submemory::rewrite_with_options(
+        &wasm,
+        &RewriteOptions::new(SUBMEMORY_SIZE).bounds_mode(BoundsMode::Mask),
+    )?;
+    let mut vm = VM::new(&wasm)?;
+    for i in 0..2 {
+        assert_eq!(vm.add_submemory()?.0, i);
+    }
+    vm.select_submemory(0)?;
+
+    let size = SUBMEMORY_SIZE as i32;
+    vm.call("store", &[Value::I32(size), Value::I32(42)])?;
+    assert_eq!(*vm.call("load", &[Value::I32(0)])?, [Value::I32(42)]);
+    vm.call("fill", &[Value::I32(size), Value::I32(16)])?;
+    assert_eq!(*vm.call("load", &[Value::I32(-64)])?, [Value::I32(0)]);
+
+    Ok(())
+}
+
+#[test]
+fn trap() -> TestResult {
+    let wasm = parse_wat(WAT)?;
+    let wasm = wasm_submemory::rewrite_with_options(
+        &wasm,
+        &RewriteOptions::new(SUBMEMORY_SIZE).bounds_mode(BoundsMode::Trap),
+    )?;
+    let mut vm = VM::new(&wasm)?;
+    for i in 0..2 {
+        assert_eq!(vm.add_submemory()?.0, i);
+    }
+    vm.select_submemory(0)?;
+
+    let last = SUBMEMORY_SIZE as i32 - 64 - 4;
+    vm.call("store", &[Value::I32(last), Value::I32(42)])?;
+    assert_eq!(*vm.call("load", &[Value::I32(last)])?, [Value::I32(42)]);
+    vm.select_submemory(1)?;
+    assert_eq!(*vm.call("load", &[Value::I32(last)])?, [Value::I32(0)]);
+
+    for addr in [last + 1, SUBMEMORY_SIZE as i32, -64, -1] {
+        assert!(vm.call("load", &[Value::I32(addr)]).is_err(), "{}", addr);
+        assert!(vm
+            .call("store", &[Value::I32(addr), Value::I32(0)])
+            .is_err());
+    }
+
+    let size = SUBMEMORY_SIZE as i32;
+    vm.call("fill", &[Value::I32(size - 16), Value::I32(16)])?;
+    vm.call("fill", &[Value::I32(size), Value::I32(0)])?;
+    assert!(vm
+        .call("fill", &[Value::I32(size + 1), Value::I32(0)])
+        .is_err());
+    assert!(vm
+        .call("fill", &[Value::I32(size), Value::I32(16)])
+        .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn trap_counter() -> TestResult {
+    let testcases = &[
+        Testcase {
+            name: "rust",
+            wasm: include_bytes!("../testdata/wasm/rust/i32_counter.wasm"),
+        },
+        Testcase {
+            name: "c",
+            wasm: include_bytes!("../testdata/wasm/c/i32_counter.wasm"),
+        },
+        Testcase {
+            name: "zig",
+            wasm: include_bytes!("../testdata/wasm/zig/i32_counter.wasm"),
+        },
+    ];
+
+    for testcase in testcases {
+        let wasm = wasm_submemory::rewrite_with_options(
+            testcase.wasm,
+            &RewriteOptions::new(SUBMEMORY_SIZE).bounds_mode(BoundsMode::Trap),
+        )?;
+        let mut vm = VM::new(&wasm)?;
+        for i in 0..10 {
+            assert_eq!(vm.add_submemory()?.0, i);
+        }
+        for i in 1..=10 {
+            for j in 0..10 {
+                vm.select_submemory(j)?;
+                let ret = vm.call("entry", &[])?;
+                assert_eq!(*ret, [Value::I32(i)], "{} {}", testcase.name, i);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn trap_allocation() -> TestResult {
+    let testcases = &[
+        Testcase {
+            name: "rust",
+            wasm: include_bytes!("../testdata/wasm/rust/allocation.wasm"),
+        },
+        Testcase {
+            name: "zig",
+            wasm: include_bytes!("../testdata/wasm/zig/allocation.wasm"),
+        },
+    ];
+
+    for testcase in testcases {
+        let wasm = wasm_submemory::rewrite_with_options(
+            testcase.wasm,
+            &RewriteOptions::new(SUBMEMORY_SIZE).bounds_mode(BoundsMode::Trap),
+        )?;
+        let mut vm = VM::new(&wasm)?;
+        for i in 0..10 {
+            assert_eq!(vm.add_submemory()?.0, i);
+        }
+        for i in 0..10 {
+            vm.select_submemory(i)?;
+            let ret = vm.call("entry", &[])?;
+            assert_eq!(*ret, [Value::I32(42)], "{} {}", testcase.name, i);
+        }
+    }
+
+    Ok(())
+}
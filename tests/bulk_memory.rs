@@ -0,0 +1,236 @@
+mod common;
+
+use crate::common::*;
+use testresult::TestResult;
+use wasmer::Value;
+
+struct Testcase<'a> {
+    name: &'a str,
+    wasm: &'a [u8],
+}
+
+#[test]
+fn memory_fill() -> TestResult {
+    let wasm = parse_wat(
+        r#"
+(module
+  (type (;0;) (func (param i32) (result i32)))
+  (func $entry (type 0) (param i32) (result i32)
+    i32.const 64
+    local.get 0
+    i32.const 16
+    memory.fill
+    i32.const 72
+    i32.load)
+  (memory (;0;) 1)
+  (export "memory" (memory 0))
+  (export "entry" (func $entry)))
+            "#,
+    )?;
+
+    let wasm = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE)?;
+    let mut vm = VM::new(&wasm)?;
+    for i in 0..10 {
+        assert_eq!(vm.add_submemory()?.0, i);
+    }
+    for i in 0..10 {
+        vm.select_submemory(i)?;
+        let ret = vm.call("entry", &[Value::I32(i as i32 + 1)])?;
+        assert_eq!(*ret, [Value::I32(0x01010101 * (i as i32 + 1))], "{}", i);
+    }
+
+    let view = vm.memory.view(&mut vm.store);
+    for i in 0..10 {
+        let base = (WASM_PAGE_SIZE * 2 + SUBMEMORY_SIZE * i) as u64;
+        let mut buf = [0; 20];
+        view.read(base + 62, &mut buf)?;
+        let fill = i as u8 + 1;
+        assert_eq!(buf[..2], [0, 0], "{}", i);
+        assert_eq!(buf[2..18], [fill; 16], "{}", i);
+        assert_eq!(buf[18..], [0, 0], "{}", i);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn memory_copy() -> TestResult {
+    let wasm = parse_wat(
+        r#"
+(module
+  (type (;0;) (func (result i32)))
+  (func $entry (type 0) (result i32)
+    i32.const 0
+    i32.const 0
+    i32.load offset=64
+    i32.const 1
+    i32.add
+    i32.store offset=64
+    i32.const 128
+    i32.const 64
+    i32.const 4
+    memory.copy
+    i32.const 128
+    i32.load)
+  (memory (;0;) 1)
+  (export "memory" (memory 0))
+  (export "entry" (func $entry)))
+            "#,
+    )?;
+
+    let wasm = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE)?;
+    let mut vm = VM::new(&wasm)?;
+    for i in 0..10 {
+        assert_eq!(vm.add_submemory()?.0, i);
+    }
+    for i in 1..=10 {
+        for j in 0..10 {
+            vm.select_submemory(j)?;
+            let ret = vm.call("entry", &[])?;
+            assert_eq!(*ret, [Value::I32(i)], "{} {}", i, j);
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn fill_and_copy() -> TestResult {
+    let wat_wasm = parse_wat(
+        r#"
+(module
+  (type (;0;) (func (param i32 i32)))
+  (type (;1;) (func (param i32)))
+  (type (;2;) (func (param i32) (result i32)))
+  (func $fill (type 0) (param i32 i32)
+    i32.const 1024
+    local.get 0
+    local.get 1
+    i32.const 64
+    local.get 1
+    i32.const 64
+    i32.lt_u
+    select
+    memory.fill)
+  (func $copy (type 1) (param i32)
+    i32.const 1088
+    i32.const 1024
+    local.get 0
+    i32.const 64
+    local.get 0
+    i32.const 64
+    i32.lt_u
+    select
+    memory.copy)
+  (func $entry (type 2) (param i32) (result i32)
+    local.get 0
+    i32.const 128
+    i32.rem_u
+    i32.load8_u offset=1024)
+  (memory (;0;) 1)
+  (export "memory" (memory 0))
+  (export "fill" (func $fill))
+  (export "copy" (func $copy))
+  (export "entry" (func $entry)))
+            "#,
+    )?;
+
+    let testcases = &[
+        Testcase {
+            name: "wat",
+            wasm: &wat_wasm,
+        },
+        Testcase {
+            name: "rust",
+            wasm: include_bytes!("../testdata/wasm/rust/bulk_memory.wasm"),
+        },
+    ];
+
+    for testcase in testcases {
+        let wasm = wasm_submemory::rewrite(testcase.wasm, SUBMEMORY_SIZE)?;
+        let mut vm = VM::new(&wasm)?;
+        for i in 0..10 {
+            assert_eq!(vm.add_submemory()?.0, i);
+        }
+        for i in 0..10 {
+            vm.select_submemory(i)?;
+            let len = 16 + i as i32;
+            vm.call("fill", &[Value::I32(i as i32 + 1), Value::I32(len)])?;
+            vm.call("copy", &[Value::I32(8)])?;
+        }
+        for i in 0..10 {
+            vm.select_submemory(i)?;
+            let len = 16 + i as i32;
+            let expected = [
+                (0, i as i32 + 1),
+                (len - 1, i as i32 + 1),
+                (len, 0),
+                (64, i as i32 + 1),
+                (71, i as i32 + 1),
+                (72, 0),
+            ];
+            for (index, value) in expected {
+                let ret = vm.call("entry", &[Value::I32(index)])?;
+                assert_eq!(
+                    *ret,
+                    [Value::I32(value)],
+                    "{} {} {}",
+                    testcase.name,
+                    i,
+                    index
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn out_of_bounds() -> TestResult {
+    let wasm = parse_wat(
+        r#"
+(module
+  (type (;0;) (func (param i32 i32)))
+  (func $fill (type 0) (param i32 i32)
+    local.get 0
+    i32.const 0
+    local.get 1
+    memory.fill)
+  (func $copy_to (type 0) (param i32 i32)
+    local.get 0
+    i32.const 0
+    local.get 1
+    memory.copy)
+  (func $copy_from (type 0) (param i32 i32)
+    i32.const 0
+    local.get 0
+    local.get 1
+    memory.copy)
+  (memory (;0;) 1)
+  (export "memory" (memory 0))
+  (export "fill" (func $fill))
+  (export "copy_to" (func $copy_to))
+  (export "copy_from" (func $copy_from)))
+            "#,
+    )?;
+
+    let wasm = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE)?;
+    let mut vm = VM::new(&wasm)?;
+    for i in 0..2 {
+        assert_eq!(vm.add_submemory()?.0, i);
+    }
+    vm.select_submemory(0)?;
+
+    let end = SUBMEMORY_SIZE as i32;
+    for func in ["fill", "copy_to", "copy_from"] {
+        vm.call(func, &[Value::I32(end - 16), Value::I32(16)])?;
+        vm.call(func, &[Value::I32(end), Value::I32(0)])?;
+        assert!(vm
+            .call(func, &[Value::I32(end - 16), Value::I32(17)])
+            .is_err());
+        assert!(vm.call(func, &[Value::I32(0), Value::I32(-1)]).is_err());
+    }
+
+    Ok(())
+}
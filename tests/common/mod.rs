@@ -1,10 +1,65 @@
 #![allow(dead_code)]
 
-use wasmer::{imports, Instance, Module, Store, Value};
+use wasmer::{imports, Imports, Instance, Module, Store, Value};
 
 pub const WASM_PAGE_SIZE: u32 = wasm_submemory::WASM_PAGE_SIZE;
 pub const SUBMEMORY_SIZE: u32 = 1 << 20;
 
+/// A module with a mutable global, an active and a passive data segment and
+/// a function that grows memory, for checking the state of submemories.
+pub const SUBMEMORY_WAT: &str = r#"
+(module
+  (type (;0;) (func (result i32)))
+  (func $increment (type 0) (result i32)
+    global.get $counter
+    i32.const 1
+    i32.add
+    global.set $counter
+    i32.const 0
+    i32.const 0
+    i32.load offset=64
+    global.get $counter
+    i32.add
+    i32.store offset=64
+    i32.const 0
+    i32.load offset=64)
+  (func $grow (type 0) (result i32)
+    i32.const 1
+    memory.grow
+    drop
+    i32.const 65536
+    i32.const 7
+    i32.store
+    memory.size)
+  (func $extra (type 0) (result i32)
+    memory.size
+    i32.const 1
+    i32.gt_u
+    if (result i32)
+      i32.const 65536
+      i32.load
+    else
+      i32.const -1
+    end)
+  (func $init (type 0) (result i32)
+    i32.const 128
+    i32.const 0
+    i32.const 4
+    memory.init $passive
+    data.drop $passive
+    i32.const 0
+    i32.load offset=128)
+  (global $counter (mut i32) (i32.const 0))
+  (memory (;0;) 1)
+  (data $.data (i32.const 64) "*\00\00\00")
+  (data $passive "\05\00\00\00")
+  (export "memory" (memory 0))
+  (export "increment" (func $increment))
+  (export "grow" (func $grow))
+  (export "extra" (func $extra))
+  (export "init" (func $init)))
+"#;
+
 pub fn parse_wat(wat: &str) -> anyhow::Result<Vec<u8>> {
     Ok(wasmer::wat2wasm(wat.as_bytes())?.to_vec())
 }
@@ -18,8 +73,15 @@ pub struct VM {
 
 impl VM {
     pub fn new(wasm: &[u8]) -> anyhow::Result<Self> {
+        Self::with_imports(wasm, |_| imports! {})
+    }
+
+    pub fn with_imports(
+        wasm: &[u8],
+        make_imports: impl FnOnce(&mut Store) -> Imports,
+    ) -> anyhow::Result<Self> {
         let mut store = Store::default();
-        let import_object = imports! {};
+        let import_object = make_imports(&mut store);
         let module = Module::new(&store, wasm)?;
         let instance = Instance::new(&mut store, &module, &import_object)?;
         let memory = instance.exports.get_memory("memory")?.clone();
@@ -41,12 +103,21 @@ impl VM {
     }
 
     pub fn add_submemory(&mut self) -> anyhow::Result<(u32, u32)> {
-        match *self.call("add_submemory", &[])? {
-            [Value::I32(index), Value::I32(base_address)] => {
-                Ok((index as u32, base_address as u32))
-            }
-            _ => Err(anyhow::anyhow!("unexpected result from add_submemory")),
-        }
+        let ret = self.call("add_submemory", &[])?;
+        submemory_result("add_submemory", &ret)
+    }
+
+    pub fn fork_submemory(&mut self, src: u32) -> anyhow::Result<(u32, u32)> {
+        let ret = self.call("fork_submemory", &[Value::I32(src as i32)])?;
+        submemory_result("fork_submemory", &ret)
+    }
+
+    pub fn copy_submemory(&mut self, src: u32, dst: u32) -> anyhow::Result<()> {
+        self.call(
+            "copy_submemory",
+            &[Value::I32(src as i32), Value::I32(dst as i32)],
+        )?;
+        Ok(())
     }
 
     pub fn select_submemory(&mut self, index: u32) -> anyhow::Result<()> {
@@ -58,4 +129,30 @@ impl VM {
         self.call("reset_submemory", &[Value::I32(index as i32)])?;
         Ok(())
     }
+
+    pub fn remove_submemory(&mut self, index: u32) -> anyhow::Result<()> {
+        self.call("remove_submemory", &[Value::I32(index as i32)])?;
+        Ok(())
+    }
+}
+
+impl wasm_submemory::Guest for VM {
+    fn call(&mut self, name: &str, args: &[i32]) -> anyhow::Result<Vec<i32>> {
+        let args: Vec<_> = args.iter().map(|&arg| Value::I32(arg)).collect();
+        VM::call(self, name, &args)?
+            .iter()
+            .map(|value| match value {
+                Value::I32(i) => Ok(*i),
+                _ => Err(anyhow::anyhow!("unexpected result from {}", name)),
+            })
+            .collect()
+    }
+}
+
+fn submemory_result(func_name: &str, ret: &[Value]) -> anyhow::Result<(u32, u32)> {
+    match *ret {
+        [Value::I32(-1), Value::I32(-1)] => Err(anyhow::anyhow!("{} failed", func_name)),
+        [Value::I32(index), Value::I32(base_address)] => Ok((index as u32, base_address as u32)),
+        _ => Err(anyhow::anyhow!("unexpected result from {}", func_name)),
+    }
 }
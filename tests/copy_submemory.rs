@@ -0,0 +1,65 @@
+mod common;
+
+use crate::common::*;
+use testresult::TestResult;
+use wasmer::Value;
+
+#[test]
+fn checkpoint_restore() -> TestResult {
+    let wasm = parse_wat(SUBMEMORY_WAT)?;
+    let wasm = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE)?;
+    let mut vm = VM::new(&wasm)?;
+    for i in 0..2 {
+        assert_eq!(vm.add_submemory()?.0, i);
+    }
+    vm.select_submemory(0)?;
+    assert_eq!(*vm.call("increment", &[])?, [Value::I32(43)]);
+
+    // Checkpoint the selected submemory into submemory 1.
+    vm.copy_submemory(0, 1)?;
+    assert_eq!(*vm.call("increment", &[])?, [Value::I32(45)]);
+    assert_eq!(*vm.call("grow", &[])?, [Value::I32(2)]);
+
+    // Restore the selected submemory from the checkpoint, twice.
+    for _ in 0..2 {
+        vm.copy_submemory(1, 0)?;
+        assert_eq!(*vm.call("extra", &[])?, [Value::I32(-1)]);
+        assert_eq!(*vm.call("increment", &[])?, [Value::I32(45)]);
+        assert_eq!(*vm.call("grow", &[])?, [Value::I32(2)]);
+    }
+
+    // The checkpoint itself is unchanged.
+    vm.select_submemory(1)?;
+    assert_eq!(*vm.call("extra", &[])?, [Value::I32(-1)]);
+    assert_eq!(*vm.call("increment", &[])?, [Value::I32(45)]);
+
+    Ok(())
+}
+
+#[test]
+fn unselected() -> TestResult {
+    let wasm = parse_wat(SUBMEMORY_WAT)?;
+    let wasm = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE)?;
+    let mut vm = VM::new(&wasm)?;
+    for i in 0..3 {
+        assert_eq!(vm.add_submemory()?.0, i);
+    }
+    vm.select_submemory(0)?;
+    assert_eq!(*vm.call("increment", &[])?, [Value::I32(43)]);
+    assert_eq!(*vm.call("grow", &[])?, [Value::I32(2)]);
+    vm.select_submemory(2)?;
+
+    // Neither submemory is selected.
+    vm.copy_submemory(0, 1)?;
+    vm.copy_submemory(1, 1)?;
+    vm.select_submemory(1)?;
+    assert_eq!(*vm.call("extra", &[])?, [Value::I32(7)]);
+    assert_eq!(*vm.call("increment", &[])?, [Value::I32(45)]);
+
+    vm.remove_submemory(2)?;
+    assert!(vm.copy_submemory(2, 0).is_err());
+    assert!(vm.copy_submemory(0, 2).is_err());
+    assert!(vm.copy_submemory(0, 3).is_err());
+
+    Ok(())
+}
@@ -0,0 +1,51 @@
+mod common;
+
+use crate::common::*;
+use testresult::TestResult;
+use wasm_submemory::{Error, RewriteOptions};
+
+const WAT: &str = r#"
+(module
+  (type (;0;) (func (result i32)))
+  (func $entry (type 0) (result i32)
+    i32.const 0
+    i32.load offset=64)
+  (memory (;0;) 1)
+  (data $.data (i32.const 64) "*\00\00\00")
+  (export "memory" (memory 0))
+  (export "entry" (func $entry)))
+"#;
+
+#[test]
+fn rewritten_module() -> TestResult {
+    let wasm = parse_wat(WAT)?;
+    let wasm = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE)?;
+    let ret = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE);
+    assert!(matches!(ret, Err(Error::AlreadyRewritten)));
+
+    // Without the custom section, the injected exports give it away.
+    let mut module = walrus::Module::from_buffer(&wasm)?;
+    module
+        .customs
+        .remove_raw(wasm_submemory::METADATA_SECTION)
+        .unwrap();
+    let wasm = module.emit_wasm();
+    let ret = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE);
+    assert!(matches!(ret, Err(Error::ExportCollision(name)) if name == "select_submemory"));
+
+    Ok(())
+}
+
+#[test]
+fn export_collision() -> TestResult {
+    let wat = WAT.replace(r#"(export "entry""#, r#"(export "reset_submemory""#);
+    let wasm = parse_wat(&wat)?;
+    let ret = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE);
+    assert!(matches!(ret, Err(Error::ExportCollision(name)) if name == "reset_submemory"));
+
+    // A prefix avoids the collision.
+    let options = RewriteOptions::new(SUBMEMORY_SIZE).export_prefix("submemory_");
+    wasm_submemory::rewrite_with_options(&wasm, &options)?;
+
+    Ok(())
+}
@@ -0,0 +1,82 @@
+mod common;
+
+use crate::common::*;
+use testresult::TestResult;
+use wasm_submemory::RewriteOptions;
+use wasmer::Value;
+
+#[test]
+fn fork() -> TestResult {
+    let wasm = parse_wat(SUBMEMORY_WAT)?;
+    let wasm = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE)?;
+    let mut vm = VM::new(&wasm)?;
+    let (_, first_base_address) = vm.add_submemory()?;
+    assert_eq!(vm.add_submemory()?.0, 1);
+    vm.select_submemory(0)?;
+    assert_eq!(*vm.call("increment", &[])?, [Value::I32(43)]);
+    assert_eq!(*vm.call("increment", &[])?, [Value::I32(45)]);
+    assert_eq!(*vm.call("grow", &[])?, [Value::I32(2)]);
+
+    // Fork the selected submemory, whose globals are live.
+    let base_address = first_base_address + SUBMEMORY_SIZE * 2;
+    assert_eq!(vm.fork_submemory(0)?, (2, base_address));
+    vm.select_submemory(2)?;
+    assert_eq!(*vm.call("extra", &[])?, [Value::I32(7)]);
+    assert_eq!(*vm.call("increment", &[])?, [Value::I32(48)]);
+    assert_eq!(*vm.call("increment", &[])?, [Value::I32(52)]);
+
+    // The source is unaffected by the fork's progress.
+    vm.select_submemory(0)?;
+    assert_eq!(*vm.call("increment", &[])?, [Value::I32(48)]);
+
+    // Fork a submemory that is not selected.
+    assert_eq!(vm.fork_submemory(2)?.0, 3);
+    vm.select_submemory(3)?;
+    assert_eq!(*vm.call("increment", &[])?, [Value::I32(57)]);
+    vm.select_submemory(1)?;
+    assert_eq!(*vm.call("extra", &[])?, [Value::I32(-1)]);
+    assert_eq!(*vm.call("increment", &[])?, [Value::I32(43)]);
+
+    Ok(())
+}
+
+#[test]
+fn reused_slot() -> TestResult {
+    let wasm = parse_wat(SUBMEMORY_WAT)?;
+    let wasm = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE)?;
+    let mut vm = VM::new(&wasm)?;
+    for i in 0..2 {
+        assert_eq!(vm.add_submemory()?.0, i);
+    }
+    vm.select_submemory(1)?;
+    assert_eq!(*vm.call("grow", &[])?, [Value::I32(2)]);
+    vm.remove_submemory(1)?;
+
+    vm.select_submemory(0)?;
+    assert_eq!(*vm.call("increment", &[])?, [Value::I32(43)]);
+    assert_eq!(vm.fork_submemory(0)?.0, 1);
+    vm.select_submemory(1)?;
+    assert_eq!(*vm.call("extra", &[])?, [Value::I32(-1)]);
+    assert_eq!(*vm.call("increment", &[])?, [Value::I32(45)]);
+
+    Ok(())
+}
+
+#[test]
+fn failure() -> TestResult {
+    let wasm = parse_wat(SUBMEMORY_WAT)?;
+    let options = RewriteOptions::new(SUBMEMORY_SIZE).max_submemories(2);
+    let wasm = wasm_submemory::rewrite_with_options(&wasm, &options)?;
+    let mut vm = VM::new(&wasm)?;
+    for i in 0..2 {
+        assert_eq!(vm.add_submemory()?.0, i);
+    }
+    assert!(vm.fork_submemory(0).is_err());
+
+    vm.remove_submemory(1)?;
+    assert!(vm.fork_submemory(1).is_err());
+    assert!(vm.fork_submemory(2).is_err());
+    assert_eq!(vm.fork_submemory(0)?.0, 1);
+
+    Ok(())
+}
@@ -0,0 +1,110 @@
+mod common;
+
+use crate::common::*;
+use testresult::TestResult;
+use wasm_submemory::{RewriteOptions, Submemories};
+use wasmer::Value;
+
+const WAT: &str = r#"
+(module
+  (type (;0;) (func (result i32)))
+  (func $increment (type 0) (result i32)
+    i32.const 0
+    i32.const 0
+    i32.load offset=64
+    i32.const 1
+    i32.add
+    i32.store offset=64
+    i32.const 0
+    i32.load offset=64)
+  (memory (;0;) 1)
+  (data $.data (i32.const 64) "*\00\00\00")
+  (export "memory" (memory 0))
+  (export "increment" (func $increment)))
+"#;
+
+fn increment(vm: &mut VM) -> anyhow::Result<Box<[Value]>> {
+    vm.call("increment", &[])
+}
+
+#[test]
+fn submemories() -> TestResult {
+    let wasm = parse_wat(WAT)?;
+    let wasm = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE)?;
+    let mut submemories = Submemories::new(VM::new(&wasm)?);
+    let ids = (0..3)
+        .map(|_| submemories.add())
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    assert_eq!(
+        ids.iter().map(|id| id.index()).collect::<Vec<_>>(),
+        [0, 1, 2]
+    );
+    assert_eq!(submemories.selected(), None);
+
+    submemories.select(ids[1])?;
+    assert_eq!(submemories.selected(), Some(ids[1]));
+    assert_eq!(*increment(submemories.guest_mut())?, [Value::I32(43)]);
+
+    let fork = submemories.fork(ids[1])?;
+    submemories.select(fork)?;
+    assert_eq!(*increment(submemories.guest_mut())?, [Value::I32(44)]);
+    submemories.copy(ids[0], fork)?;
+    assert_eq!(*increment(submemories.guest_mut())?, [Value::I32(43)]);
+
+    submemories.reset(ids[1])?;
+    submemories.remove(fork)?;
+    assert_eq!(submemories.selected(), None);
+    assert!(submemories.select(fork).is_err());
+    submemories.select(ids[1])?;
+    assert_eq!(*increment(submemories.guest_mut())?, [Value::I32(43)]);
+
+    Ok(())
+}
+
+#[test]
+fn with_selected() -> TestResult {
+    let wasm = parse_wat(WAT)?;
+    let wasm = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE)?;
+    let mut submemories = Submemories::new(VM::new(&wasm)?);
+    let a = submemories.add()?;
+    let b = submemories.add()?;
+    submemories.select(a)?;
+
+    let ret = submemories.with_selected(b, |vm| {
+        increment(vm)?;
+        increment(vm)
+    })?;
+    assert_eq!(*ret, [Value::I32(44)]);
+    assert_eq!(submemories.selected(), Some(a));
+    assert_eq!(*increment(submemories.guest_mut())?, [Value::I32(43)]);
+
+    // The previous selection is restored when the closure fails.
+    let ret = submemories.with_selected(b, |vm| {
+        increment(vm)?;
+        vm.call("missing", &[])
+    });
+    assert!(ret.is_err());
+    assert_eq!(submemories.selected(), Some(a));
+    assert_eq!(*increment(submemories.guest_mut())?, [Value::I32(44)]);
+    submemories.select(b)?;
+    assert_eq!(*increment(submemories.guest_mut())?, [Value::I32(46)]);
+
+    Ok(())
+}
+
+#[test]
+fn export_prefix() -> TestResult {
+    let wasm = parse_wat(WAT)?;
+    let options = RewriteOptions::new(SUBMEMORY_SIZE)
+        .export_prefix("guest_")
+        .max_submemories(1);
+    let wasm = wasm_submemory::rewrite_with_options(&wasm, &options)?;
+    let mut submemories = Submemories::new(VM::new(&wasm)?).export_prefix("guest_");
+    let id = submemories.add()?;
+    assert!(submemories.add().is_err());
+    assert!(submemories.fork(id).is_err());
+    submemories.select(id)?;
+    assert_eq!(*increment(submemories.guest_mut())?, [Value::I32(43)]);
+
+    Ok(())
+}
@@ -0,0 +1,144 @@
+mod common;
+
+use crate::common::*;
+use testresult::TestResult;
+use wasm_submemory::{Submemories, SubmemoryId};
+use wasmer::Value;
+
+const WAT: &str = r#"
+(module
+  (type (;0;) (func (param i32) (result i32)))
+  (func $grow (type 0) (param i32) (result i32)
+    local.get 0
+    memory.grow)
+  (memory (;0;) 2)
+  (export "memory" (memory 0))
+  (export "grow" (func $grow)))
+"#;
+
+fn grow(submemories: &mut Submemories<VM>, id: SubmemoryId, pages: i32) -> anyhow::Result<()> {
+    submemories.with_selected(id, |vm| vm.call("grow", &[Value::I32(pages)]))?;
+    Ok(())
+}
+
+#[test]
+fn exports() -> TestResult {
+    let wasm = parse_wat(WAT)?;
+    let wasm = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE)?;
+    let mut vm = VM::new(&wasm)?;
+    assert_eq!(*vm.call("submemory_count", &[])?, [Value::I32(0)]);
+    assert_eq!(*vm.call("current_submemory", &[])?, [Value::I32(-2)]);
+    assert!(vm.call("submemory_base", &[Value::I32(0)]).is_err());
+    assert!(vm.call("submemory_pages", &[Value::I32(0)]).is_err());
+    assert!(vm.call("submemory_peak_pages", &[Value::I32(0)]).is_err());
+
+    for i in 0..3 {
+        let (index, base_address) = vm.add_submemory()?;
+        assert_eq!(index, i);
+        let ret = vm.call("submemory_base", &[Value::I32(i as i32)])?;
+        assert_eq!(*ret, [Value::I32(base_address as i32)]);
+    }
+    assert_eq!(*vm.call("submemory_count", &[])?, [Value::I32(3)]);
+    vm.select_submemory(2)?;
+    assert_eq!(*vm.call("current_submemory", &[])?, [Value::I32(2)]);
+
+    Ok(())
+}
+
+#[test]
+fn pages() -> TestResult {
+    let wasm = parse_wat(WAT)?;
+    let wasm = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE)?;
+    let mut submemories = Submemories::new(VM::new(&wasm)?);
+    let a = submemories.add()?;
+    let b = submemories.add()?;
+    assert_eq!(submemories.count()?, 2);
+    assert_eq!(
+        submemories.base_address(b)?,
+        WASM_PAGE_SIZE * 3 + SUBMEMORY_SIZE
+    );
+
+    grow(&mut submemories, a, 3)?;
+    grow(&mut submemories, a, 1)?;
+    assert_eq!(submemories.current()?, a);
+    assert_eq!(submemories.pages(a)?, Some(6));
+    assert_eq!(submemories.peak_pages(a)?, 6);
+    assert_eq!(submemories.pages(b)?, Some(2));
+    assert_eq!(submemories.peak_pages(b)?, 2);
+
+    // Resetting and copying keep the high-water mark.
+    submemories.reset(a)?;
+    assert_eq!(submemories.pages(a)?, Some(2));
+    assert_eq!(submemories.peak_pages(a)?, 6);
+    grow(&mut submemories, b, 5)?;
+    submemories.copy(b, a)?;
+    assert_eq!(submemories.pages(a)?, Some(7));
+    assert_eq!(submemories.peak_pages(a)?, 7);
+    submemories.reset(b)?;
+    submemories.copy(b, a)?;
+    assert_eq!(submemories.pages(a)?, Some(2));
+    assert_eq!(submemories.peak_pages(a)?, 7);
+
+    // A removed submemory has no pages; a reused one starts afresh.
+    submemories.remove(a)?;
+    assert_eq!(submemories.pages(a)?, None);
+    assert_eq!(submemories.add()?, a);
+    assert_eq!(submemories.peak_pages(a)?, 2);
+    let fork = submemories.fork(b)?;
+    assert_eq!(submemories.peak_pages(fork)?, 2);
+    assert_eq!(submemories.count()?, 3);
+
+    Ok(())
+}
+
+#[test]
+fn nothing_selected() -> TestResult {
+    let wasm = parse_wat(
+        r#"
+(module
+  (type (;0;) (func))
+  (type (;1;) (func (param i32 i32)))
+  (type (;2;) (func (param i32) (result i32)))
+  (func $init (type 0)
+    i32.const 4
+    i32.const 7
+    i32.store)
+  (func $store (type 1) (param i32 i32)
+    local.get 0
+    local.get 1
+    i32.store)
+  (func $load (type 2) (param i32) (result i32)
+    local.get 0
+    i32.load)
+  (memory (;0;) 1)
+  (export "memory" (memory 0))
+  (export "store" (func $store))
+  (export "load" (func $load))
+  (start $init))
+"#,
+    )?;
+    let wasm = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE)?;
+    let mut submemories = Submemories::new(VM::new(&wasm)?);
+    assert!(submemories.current().is_err());
+
+    // Guest memory accesses trap until a submemory is selected, rather than
+    // writing over the records in the headroom.
+    let vm = submemories.guest_mut();
+    assert!(vm.call("store", &[Value::I32(0), Value::I32(-1)]).is_err());
+    assert!(vm.call("load", &[Value::I32(4)]).is_err());
+
+    let a = submemories.add()?;
+    assert!(submemories.current().is_err());
+    submemories.select(a)?;
+    assert_eq!(submemories.current()?, a);
+    let ret = submemories.guest_mut().call("load", &[Value::I32(4)])?;
+    assert_eq!(*ret, [Value::I32(7)]);
+    assert_eq!(submemories.pages(a)?, Some(1));
+
+    submemories.remove(a)?;
+    assert!(submemories.current().is_err());
+    let vm = submemories.guest_mut();
+    assert!(vm.call("store", &[Value::I32(0), Value::I32(-1)]).is_err());
+
+    Ok(())
+}
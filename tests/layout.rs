@@ -0,0 +1,209 @@
+mod common;
+
+use crate::common::*;
+use testresult::TestResult;
+use wasm_submemory::{Error, Layout, RewriteOptions, Submemories, SubmemoryId};
+use wasmer::{imports, Function, FunctionEnv, FunctionEnvMut, Global, Memory, Value};
+
+const WAT: &str = r#"
+(module
+  (type (;0;) (func (param i32)))
+  (type (;1;) (func (param i32 i32) (result i32)))
+  (func $greet (type 0) (param i32)
+    local.get 0
+    i32.const 0x00216968
+    i32.store)
+  (func $sum (type 1) (param i32 i32) (result i32)
+    (local i32)
+    block
+      loop
+        local.get 1
+        i32.eqz
+        br_if 1
+        local.get 2
+        local.get 0
+        i32.load8_u
+        i32.add
+        local.set 2
+        local.get 0
+        i32.const 1
+        i32.add
+        local.set 0
+        local.get 1
+        i32.const 1
+        i32.sub
+        local.set 1
+        br 0
+      end
+    end
+    local.get 2)
+  (memory (;0;) 2)
+  (export "memory" (memory 0))
+  (export "greet" (func $greet))
+  (export "sum" (func $sum)))
+"#;
+
+#[test]
+fn read_write() -> TestResult {
+    let wasm = parse_wat(WAT)?;
+    let options = RewriteOptions::new(SUBMEMORY_SIZE);
+    let (wasm, layout) = wasm_submemory::rewrite_with_layout(&wasm, &options)?;
+    assert_eq!(layout, Layout::new(SUBMEMORY_SIZE, WASM_PAGE_SIZE, 2)?);
+    let mut submemories = Submemories::new(VM::new(&wasm)?);
+    let ids = (0..3)
+        .map(|_| submemories.add())
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    for (i, &id) in ids.iter().enumerate() {
+        assert_eq!(
+            layout.base_address(id),
+            (WASM_PAGE_SIZE * 3 + SUBMEMORY_SIZE * i as u32) as u64
+        );
+    }
+
+    // The guest writes, the host reads.
+    let address = SUBMEMORY_SIZE - 4;
+    submemories.with_selected(ids[1], |vm| vm.call("greet", &[Value::I32(address as i32)]))?;
+    let vm = submemories.guest_mut();
+    let memory = vm.memory.view(&mut vm.store).copy_to_vec()?;
+    assert_eq!(layout.read(&memory, ids[1], address, 4)?, b"hi!\0");
+    assert_eq!(layout.read(&memory, ids[0], address, 4)?, [0; 4]);
+
+    // The host writes, the guest reads.
+    let mut memory = memory;
+    layout.write(&mut memory, ids[2], 100, &[1, 2, 3])?;
+    let range = layout.translate(ids[2], 100, 3)?;
+    let vm = submemories.guest_mut();
+    vm.memory.view(&mut vm.store).write(
+        range.start,
+        &memory[range.start as usize..range.end as usize],
+    )?;
+    let ret = submemories.with_selected(ids[2], |vm| {
+        vm.call("sum", &[Value::I32(100), Value::I32(3)])
+    })?;
+    assert_eq!(*ret, [Value::I32(6)]);
+
+    Ok(())
+}
+
+#[test]
+fn out_of_range() -> TestResult {
+    let wasm = parse_wat(WAT)?;
+    let options = RewriteOptions::new(SUBMEMORY_SIZE);
+    let (wasm, layout) = wasm_submemory::rewrite_with_layout(&wasm, &options)?;
+    let mut submemories = Submemories::new(VM::new(&wasm)?);
+    let id = submemories.add()?;
+    let vm = submemories.guest_mut();
+    let mut memory = vm.memory.view(&mut vm.store).copy_to_vec()?;
+
+    layout.translate(id, SUBMEMORY_SIZE - 4, 4)?;
+    assert!(matches!(
+        layout.translate(id, SUBMEMORY_SIZE - 4, 5),
+        Err(Error::OutOfRange { len: 5, .. })
+    ));
+    assert!(layout.translate(id, u32::MAX, 2).is_err());
+    assert!(layout.read(&memory, id, SUBMEMORY_SIZE, 1).is_err());
+    assert!(layout
+        .write(&mut memory, id, SUBMEMORY_SIZE - 1, &[0; 2])
+        .is_err());
+
+    // The submemory must be within the given memory.
+    let end = layout.base_address(id) as usize + 8;
+    layout.read(&memory[..end], id, 0, 8)?;
+    assert!(matches!(
+        layout.read(&memory[..end], id, 0, 9),
+        Err(Error::PastEndOfMemory { id: past, .. }) if past == id
+    ));
+
+    assert!(Layout::new(SUBMEMORY_SIZE, 1000, 1).is_err());
+    assert!(Layout::new(WASM_PAGE_SIZE, WASM_PAGE_SIZE, 2).is_err());
+
+    Ok(())
+}
+
+struct PrintEnv {
+    layout: Layout,
+    memory: Option<Memory>,
+    index: Option<Global>,
+    printed: Vec<(u32, Vec<u8>)>,
+}
+
+fn print(mut env: FunctionEnvMut<PrintEnv>, ptr: i32, len: i32) {
+    let (env, mut store) = env.data_and_store_mut();
+    // The import only knows the raw index of the submemory that called it.
+    let index = env.index.as_ref().unwrap().get(&mut store).unwrap_i32() as u32;
+    let range = env
+        .layout
+        .translate(SubmemoryId::new(index), ptr as u32, len as u32)
+        .unwrap();
+    let mut bytes = vec![0; len as usize];
+    env.memory
+        .as_ref()
+        .unwrap()
+        .view(&mut store)
+        .read(range.start, &mut bytes)
+        .unwrap();
+    env.printed.push((index, bytes));
+}
+
+#[test]
+fn host_function() -> TestResult {
+    let wasm = parse_wat(
+        r#"
+(module
+  (type (;0;) (func (param i32 i32)))
+  (import "env" "print" (func $print (type 0)))
+  (func $send (type 0) (param i32 i32)
+    local.get 0
+    local.get 1
+    i32.store
+    local.get 0
+    i32.const 4
+    call $print)
+  (memory (;0;) 1)
+  (export "memory" (memory 0))
+  (export "send" (func $send)))
+"#,
+    )?;
+    let options = RewriteOptions::new(SUBMEMORY_SIZE).export_internals(true);
+    let (wasm, layout) = wasm_submemory::rewrite_with_layout(&wasm, &options)?;
+    let mut env = None;
+    let mut vm = VM::with_imports(&wasm, |store| {
+        let print_env = FunctionEnv::new(
+            store,
+            PrintEnv {
+                layout,
+                memory: None,
+                index: None,
+                printed: Vec::new(),
+            },
+        );
+        env = Some(print_env.clone());
+        imports! {
+            "env" => {
+                "print" => Function::new_typed_with_env(store, &print_env, print),
+            }
+        }
+    })?;
+    let env = env.unwrap();
+    let index = vm.instance.exports.get_global("__submemory_index")?.clone();
+    env.as_mut(&mut vm.store).memory = Some(vm.memory.clone());
+    env.as_mut(&mut vm.store).index = Some(index);
+
+    for _ in 0..3 {
+        vm.add_submemory()?;
+    }
+    for i in [2, 0, 1] {
+        vm.select_submemory(i)?;
+        vm.call("send", &[Value::I32(64), Value::I32(0x00216968 + i as i32)])?;
+    }
+    assert_eq!(
+        env.as_ref(&vm.store).printed,
+        [
+            (2, b"ji!\0".to_vec()),
+            (0, b"hi!\0".to_vec()),
+            (1, b"ii!\0".to_vec()),
+        ]
+    );
+
+    Ok(())
+}
@@ -0,0 +1,69 @@
+mod common;
+
+use crate::common::*;
+use testresult::TestResult;
+use wasmer::Value;
+
+const WAT: &str = r#"
+(module
+  (type (;0;) (func (param i32) (result i32)))
+  (type (;1;) (func (result i32)))
+  (func $grow (type 0) (param i32) (result i32)
+    local.get 0
+    memory.grow)
+  (func $size (type 1) (result i32)
+    memory.size)
+  (memory (;0;) MEMORY)
+  (export "memory" (memory 0))
+  (export "grow" (func $grow))
+  (export "size" (func $size)))
+"#;
+
+#[test]
+fn submemory_full() -> TestResult {
+    let wasm = parse_wat(&WAT.replace("MEMORY", "1"))?;
+    let wasm = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE)?;
+    let mut vm = VM::new(&wasm)?;
+    for i in 0..2 {
+        assert_eq!(vm.add_submemory()?.0, i);
+    }
+    let max_pages = (SUBMEMORY_SIZE / WASM_PAGE_SIZE) as i32;
+
+    vm.select_submemory(0)?;
+    assert_eq!(
+        *vm.call("grow", &[Value::I32(max_pages)])?,
+        [Value::I32(-1)]
+    );
+    assert_eq!(*vm.call("grow", &[Value::I32(-1)])?, [Value::I32(-1)]);
+    assert_eq!(*vm.call("grow", &[Value::I32(1)])?, [Value::I32(1)]);
+    assert_eq!(
+        *vm.call("grow", &[Value::I32(max_pages - 2)])?,
+        [Value::I32(2)]
+    );
+    assert_eq!(*vm.call("size", &[])?, [Value::I32(max_pages)]);
+    assert_eq!(*vm.call("grow", &[Value::I32(1)])?, [Value::I32(-1)]);
+    assert_eq!(*vm.call("grow", &[Value::I32(0)])?, [Value::I32(max_pages)]);
+    assert_eq!(*vm.call("size", &[])?, [Value::I32(max_pages)]);
+
+    vm.select_submemory(1)?;
+    assert_eq!(*vm.call("size", &[])?, [Value::I32(1)]);
+    assert_eq!(*vm.call("grow", &[Value::I32(1)])?, [Value::I32(1)]);
+
+    Ok(())
+}
+
+#[test]
+fn declared_maximum() -> TestResult {
+    let wasm = parse_wat(&WAT.replace("MEMORY", "1 4"))?;
+    let wasm = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE)?;
+    let mut vm = VM::new(&wasm)?;
+    assert_eq!(vm.add_submemory()?.0, 0);
+    vm.select_submemory(0)?;
+
+    assert_eq!(*vm.call("grow", &[Value::I32(4)])?, [Value::I32(-1)]);
+    assert_eq!(*vm.call("grow", &[Value::I32(3)])?, [Value::I32(1)]);
+    assert_eq!(*vm.call("grow", &[Value::I32(1)])?, [Value::I32(-1)]);
+    assert_eq!(*vm.call("size", &[])?, [Value::I32(4)]);
+
+    Ok(())
+}
@@ -2,6 +2,7 @@ mod common;
 
 use crate::common::*;
 use testresult::TestResult;
+use wasm_submemory::Error;
 
 #[test]
 fn allowed() -> TestResult {
@@ -36,9 +37,12 @@ fn denied() -> TestResult {
     )?;
 
     let ret = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE);
-    assert!(ret
-        .unwrap_err()
-        .to_string()
-        .contains("larger than submemory size"));
+    assert!(matches!(
+        ret,
+        Err(Error::InitialMemoryTooLarge {
+            initial_pages: 17,
+            submemory_pages: 16
+        })
+    ));
     Ok(())
 }
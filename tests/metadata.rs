@@ -0,0 +1,100 @@
+mod common;
+
+use crate::common::*;
+use testresult::TestResult;
+use wasm_submemory::{BoundsMode, Error, Layout, Metadata, RewriteOptions, Submemories};
+use wasmer::Value;
+
+const WAT: &str = r#"
+(module
+  (type (;0;) (func (result i32)))
+  (func $entry (type 0) (result i32)
+    i32.const 0
+    i32.load offset=64)
+  (memory (;0;) 2)
+  (data $.data (i32.const 64) "*\00\00\00")
+  (export "memory" (memory 0))
+  (export "entry" (func $entry)))
+"#;
+
+#[test]
+fn round_trip() -> TestResult {
+    let wasm = parse_wat(WAT)?;
+    let options = RewriteOptions::new(SUBMEMORY_SIZE)
+        .bounds_mode(BoundsMode::Trap)
+        .max_submemories(100)
+        .export_prefix("guest_")
+        .export_internals(true);
+    let (wasm, layout) = wasm_submemory::rewrite_with_layout(&wasm, &options)?;
+
+    let metadata = Metadata::from_wasm(&wasm)?;
+    assert_eq!(*metadata.layout(), layout);
+    assert_eq!(layout, Layout::new(SUBMEMORY_SIZE, WASM_PAGE_SIZE, 2)?);
+    assert_eq!(metadata.max_submemories(), 100);
+    assert_eq!(metadata.bounds_mode(), BoundsMode::Trap);
+    assert_eq!(metadata.export_prefix(), "guest_");
+    assert_eq!(metadata.export_name("add_submemory"), "guest_add_submemory");
+    assert!(metadata.export_internals());
+
+    // A host configured from the metadata alone.
+    let mut submemories = Submemories::new(VM::new(&wasm)?).export_prefix(metadata.export_prefix());
+    let id = submemories.add()?;
+    let ret = submemories.with_selected(id, |vm| vm.call("entry", &[]))?;
+    assert_eq!(*ret, [Value::I32(42)]);
+    let exports = &submemories.guest().instance.exports;
+    for name in ["__submemory_base", "__submemory_index", "__submemory_count"] {
+        exports.get_global(&metadata.export_name(name))?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn defaults() -> TestResult {
+    let wasm = parse_wat(WAT)?;
+    let wasm = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE)?;
+    let metadata = Metadata::from_wasm(&wasm)?;
+    assert_eq!(metadata.bounds_mode(), BoundsMode::Mask);
+    assert_eq!(metadata.export_prefix(), "");
+    assert!(!metadata.export_internals());
+    // The default headroom has room for 5461 records, but only 4095
+    // submemories fit in the address space.
+    assert_eq!(metadata.max_submemories(), 4095);
+
+    Ok(())
+}
+
+#[test]
+fn missing_or_incompatible() -> TestResult {
+    let wasm = parse_wat(WAT)?;
+    let ret = Metadata::from_wasm(&wasm);
+    assert!(matches!(ret, Err(Error::MissingMetadata)));
+
+    let wasm = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE)?;
+    let mut module = walrus::Module::from_buffer(&wasm)?;
+    let mut section = module
+        .customs
+        .remove_raw(wasm_submemory::METADATA_SECTION)
+        .unwrap();
+    section.data[0] = 3;
+    module.customs.add(section);
+    let ret = Metadata::from_wasm(&module.emit_wasm());
+    assert!(matches!(ret, Err(Error::UnsupportedMetadataVersion(3))));
+
+    let ret = Metadata::from_wasm(b"not wasm");
+    assert!(matches!(ret, Err(Error::Parse(_))));
+
+    Ok(())
+}
+
+#[test]
+fn without_parsing() -> TestResult {
+    // The metadata is read without validating the rest of the module.
+    let mut wasm = wasm_submemory::rewrite(&parse_wat(WAT)?, SUBMEMORY_SIZE)?;
+    wasm.extend([0x0a, 0xff, 0x01]);
+    assert!(walrus::Module::from_buffer(&wasm).is_err());
+    let metadata = Metadata::from_wasm(&wasm)?;
+    assert_eq!(metadata.layout().submemory_size(), SUBMEMORY_SIZE);
+
+    Ok(())
+}
@@ -0,0 +1,109 @@
+mod common;
+
+use crate::common::*;
+use testresult::TestResult;
+use wasm_submemory::{Error, RewriteOptions};
+use wasmer::Value;
+
+const WAT: &str = r#"
+(module
+  (type (;0;) (func (result i32)))
+  (func $entry (type 0) (result i32)
+    i32.const 0
+    i32.load offset=64)
+  (memory (;0;) 1)
+  (data $.data (i32.const 64) "*\00\00\00")
+  (export "memory" (memory 0))
+  (export "entry" (func $entry)))
+"#;
+
+#[test]
+fn export_prefix() -> TestResult {
+    let wasm = parse_wat(WAT)?;
+    let options = RewriteOptions::new(SUBMEMORY_SIZE).export_prefix("guest_");
+    let wasm = wasm_submemory::rewrite_with_options(&wasm, &options)?;
+    let mut vm = VM::new(&wasm)?;
+    assert!(vm.call("add_submemory", &[]).is_err());
+
+    let ret = vm.call("guest_add_submemory", &[])?;
+    assert_eq!(ret[0], Value::I32(0));
+    vm.call("guest_select_submemory", &[Value::I32(0)])?;
+    vm.call("guest_reset_submemory", &[Value::I32(0)])?;
+    assert_eq!(*vm.call("entry", &[])?, [Value::I32(42)]);
+
+    Ok(())
+}
+
+#[test]
+fn headroom_size() -> TestResult {
+    let wasm = parse_wat(WAT)?;
+    let options = RewriteOptions::new(SUBMEMORY_SIZE).headroom_size(WASM_PAGE_SIZE * 4);
+    let rewritten = wasm_submemory::rewrite_with_options(&wasm, &options)?;
+    let mut vm = VM::new(&rewritten)?;
+
+    let base_address = |i| WASM_PAGE_SIZE * 5 + SUBMEMORY_SIZE * i;
+    for i in 0..10 {
+        assert_eq!(vm.add_submemory()?, (i, base_address(i)));
+    }
+    for i in 0..10 {
+        vm.select_submemory(i)?;
+        assert_eq!(*vm.call("entry", &[])?, [Value::I32(42)]);
+    }
+
+    for headroom_size in [0, 1000, WASM_PAGE_SIZE + 1] {
+        let options = RewriteOptions::new(SUBMEMORY_SIZE).headroom_size(headroom_size);
+        let ret = wasm_submemory::rewrite_with_options(&wasm, &options);
+        assert!(matches!(ret, Err(Error::InvalidHeadroomSize(size)) if size == headroom_size));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn max_submemories() -> TestResult {
+    let wasm = parse_wat(WAT)?;
+    let options = RewriteOptions::new(SUBMEMORY_SIZE).max_submemories(1000);
+    wasm_submemory::rewrite_with_options(&wasm, &options)?;
+
+    let options = RewriteOptions::new(SUBMEMORY_SIZE)
+        .max_submemories(100_000)
+        .headroom_size(WASM_PAGE_SIZE);
+    let ret = wasm_submemory::rewrite_with_options(&wasm, &options);
+    assert!(matches!(
+        ret,
+        Err(Error::HeadroomTooSmall {
+            max_submemories: 100_000,
+            ..
+        })
+    ));
+
+    let options = options.headroom_size(WASM_PAGE_SIZE * 32);
+    wasm_submemory::rewrite_with_options(&wasm, &options)?;
+
+    Ok(())
+}
+
+#[test]
+fn export_internals() -> TestResult {
+    let wasm = parse_wat(WAT)?;
+    let options = RewriteOptions::new(SUBMEMORY_SIZE).export_internals(true);
+    let wasm = wasm_submemory::rewrite_with_options(&wasm, &options)?;
+    let mut vm = VM::new(&wasm)?;
+    for i in 0..3 {
+        assert_eq!(vm.add_submemory()?.0, i);
+    }
+    vm.select_submemory(2)?;
+
+    let exports = &vm.instance.exports;
+    let base = exports.get_global("__submemory_base")?;
+    let index = exports.get_global("__submemory_index")?;
+    let count = exports.get_global("__submemory_count")?;
+    assert_eq!(
+        base.get(&mut vm.store),
+        Value::I32((WASM_PAGE_SIZE * 2 + SUBMEMORY_SIZE * 2) as i32)
+    );
+    assert_eq!(index.get(&mut vm.store), Value::I32(2));
+    assert_eq!(count.get(&mut vm.store), Value::I32(3));
+
+    Ok(())
+}
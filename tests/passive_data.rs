@@ -0,0 +1,221 @@
+mod common;
+
+use crate::common::*;
+use testresult::TestResult;
+use wasm_submemory::{BoundsMode, RewriteOptions};
+use wasmer::Value;
+
+#[test]
+fn memory_init() -> TestResult {
+    let wasm = parse_wat(
+        r#"
+(module
+  (type (;0;) (func (param i32) (result i32)))
+  (func $entry (type 0) (param i32) (result i32)
+    local.get 0
+    i32.const 0
+    i32.const 4
+    memory.init $.data
+    local.get 0
+    i32.load)
+  (memory (;0;) 1)
+  (data $.data "*\00\00\00")
+  (export "memory" (memory 0))
+  (export "entry" (func $entry)))
+            "#,
+    )?;
+
+    let wasm = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE)?;
+    let mut vm = VM::new(&wasm)?;
+    for i in 0..10 {
+        assert_eq!(vm.add_submemory()?.0, i);
+    }
+    for i in 0..10 {
+        vm.select_submemory(i)?;
+        let ret = vm.call("entry", &[Value::I32(64 * i as i32)])?;
+        assert_eq!(*ret, [Value::I32(42)], "{}", i);
+    }
+
+    let view = vm.memory.view(&mut vm.store);
+    for i in 0..10 {
+        let base = (WASM_PAGE_SIZE * 2 + SUBMEMORY_SIZE * i) as u64;
+        let mut buf = [0; 4];
+        view.read(base + 64 * i as u64, &mut buf)?;
+        assert_eq!(buf, [42, 0, 0, 0], "{}", i);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn data_drop() -> TestResult {
+    let wasm = parse_wat(
+        r#"
+(module
+  (type (;0;) (func (param i32)))
+  (type (;1;) (func))
+  (func $init (type 0) (param i32)
+    i32.const 64
+    i32.const 0
+    local.get 0
+    memory.init $.data)
+  (func $drop (type 1)
+    data.drop $.data)
+  (memory (;0;) 1)
+  (data $.data "*\00\00\00")
+  (export "memory" (memory 0))
+  (export "init" (func $init))
+  (export "drop" (func $drop)))
+            "#,
+    )?;
+
+    let wasm = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE)?;
+    let mut vm = VM::new(&wasm)?;
+    for i in 0..2 {
+        assert_eq!(vm.add_submemory()?.0, i);
+    }
+
+    vm.select_submemory(0)?;
+    vm.call("init", &[Value::I32(4)])?;
+    vm.call("drop", &[])?;
+    assert!(vm.call("init", &[Value::I32(4)]).is_err());
+    vm.call("init", &[Value::I32(0)])?;
+
+    vm.select_submemory(1)?;
+    vm.call("init", &[Value::I32(4)])?;
+    vm.call("drop", &[])?;
+    assert!(vm.call("init", &[Value::I32(4)]).is_err());
+
+    vm.select_submemory(0)?;
+    assert!(vm.call("init", &[Value::I32(4)]).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn dropped_out_of_range() -> TestResult {
+    let wasm = parse_wat(
+        r#"
+(module
+  (type (;0;) (func (param i32)))
+  (type (;1;) (func))
+  (func $init (type 0) (param i32)
+    local.get 0
+    i32.const 0
+    i32.const 0
+    memory.init $.data)
+  (func $drop (type 1)
+    data.drop $.data)
+  (memory (;0;) 1)
+  (data $.data "*\00\00\00")
+  (export "memory" (memory 0))
+  (export "init" (func $init))
+  (export "drop" (func $drop)))
+            "#,
+    )?;
+
+    let options = RewriteOptions::new(SUBMEMORY_SIZE).bounds_mode(BoundsMode::Trap);
+    let wasm = wasm_submemory::rewrite_with_options(&wasm, &options)?;
+    let mut vm = VM::new(&wasm)?;
+    vm.add_submemory()?;
+    vm.select_submemory(0)?;
+    vm.call("drop", &[])?;
+
+    // An empty memory.init of a dropped segment still traps if the
+    // destination is out of bounds.
+    vm.call("init", &[Value::I32(SUBMEMORY_SIZE as i32)])?;
+    assert!(vm
+        .call("init", &[Value::I32(SUBMEMORY_SIZE as i32 + 1)])
+        .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn add_after_drop() -> TestResult {
+    let wasm = parse_wat(
+        r#"
+(module
+  (type (;0;) (func (param i32)))
+  (type (;1;) (func))
+  (func $init (type 0) (param i32)
+    i32.const 64
+    i32.const 0
+    local.get 0
+    memory.init $.data)
+  (func $drop (type 1)
+    data.drop $.data)
+  (memory (;0;) 1)
+  (data $.data "*\00\00\00")
+  (export "memory" (memory 0))
+  (export "init" (func $init))
+  (export "drop" (func $drop)))
+            "#,
+    )?;
+
+    let wasm = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE)?;
+    let mut vm = VM::new(&wasm)?;
+    assert_eq!(vm.add_submemory()?.0, 0);
+    vm.select_submemory(0)?;
+    vm.call("drop", &[])?;
+
+    assert_eq!(vm.add_submemory()?.0, 1);
+    vm.select_submemory(1)?;
+    vm.call("init", &[Value::I32(4)])?;
+
+    Ok(())
+}
+
+#[test]
+fn start_function() -> TestResult {
+    // Like the `__wasm_init_memory` start function emitted by bulk memory
+    // toolchains.
+    let wasm = parse_wat(
+        r#"
+(module
+  (type (;0;) (func))
+  (type (;1;) (func (param i32) (result i32)))
+  (type (;2;) (func (param i32)))
+  (func $__wasm_init_memory (type 0)
+    i32.const 1024
+    i32.const 0
+    i32.const 4
+    memory.init $.data
+    data.drop $.data)
+  (func $load (type 1) (param i32) (result i32)
+    local.get 0
+    i32.load)
+  (func $init (type 2) (param i32)
+    i32.const 64
+    i32.const 0
+    local.get 0
+    memory.init $.data)
+  (memory (;0;) 1)
+  (data $.data "*\00\00\00")
+  (start $__wasm_init_memory)
+  (export "memory" (memory 0))
+  (export "load" (func $load))
+  (export "init" (func $init)))
+            "#,
+    )?;
+
+    let wasm = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE)?;
+    let mut vm = VM::new(&wasm)?;
+    for i in 0..3 {
+        assert_eq!(vm.add_submemory()?.0, i);
+    }
+    for i in 0..3 {
+        vm.select_submemory(i)?;
+        let ret = vm.call("load", &[Value::I32(1024)])?;
+        assert_eq!(*ret, [Value::I32(42)], "{}", i);
+        assert_eq!(
+            *vm.call("submemory_pages", &[Value::I32(i as i32)])?,
+            [Value::I32(1)]
+        );
+        // The segment was dropped in the initial state of every submemory.
+        vm.call("init", &[Value::I32(0)])?;
+        assert!(vm.call("init", &[Value::I32(4)]).is_err(), "{}", i);
+    }
+
+    Ok(())
+}
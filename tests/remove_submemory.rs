@@ -0,0 +1,112 @@
+mod common;
+
+use crate::common::*;
+use testresult::TestResult;
+use wasmer::Value;
+
+#[test]
+fn reuse() -> TestResult {
+    let wasm = parse_wat(SUBMEMORY_WAT)?;
+    let wasm = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE)?;
+    let mut vm = VM::new(&wasm)?;
+    let mut base_addresses = vec![];
+    for i in 0..3 {
+        let (index, base_address) = vm.add_submemory()?;
+        assert_eq!(index, i);
+        base_addresses.push(base_address);
+        vm.select_submemory(i)?;
+        for _ in 0..=i {
+            vm.call("increment", &[])?;
+        }
+    }
+    vm.select_submemory(1)?;
+    assert_eq!(*vm.call("grow", &[])?, [Value::I32(2)]);
+    assert_eq!(*vm.call("extra", &[])?, [Value::I32(7)]);
+    let memory_size = vm.memory.view(&mut vm.store).data_size();
+
+    vm.remove_submemory(1)?;
+    vm.remove_submemory(0)?;
+
+    // Removed submemories are reused most recent first, without growing
+    // memory, and start out in their initial state.
+    assert_eq!(vm.add_submemory()?, (0, base_addresses[0]));
+    assert_eq!(vm.add_submemory()?, (1, base_addresses[1]));
+    assert_eq!(vm.memory.view(&mut vm.store).data_size(), memory_size);
+    for i in 0..2 {
+        vm.select_submemory(i)?;
+        assert_eq!(*vm.call("increment", &[])?, [Value::I32(43)]);
+    }
+    vm.select_submemory(1)?;
+    assert_eq!(*vm.call("extra", &[])?, [Value::I32(-1)]);
+    assert_eq!(*vm.call("grow", &[])?, [Value::I32(2)]);
+    assert_eq!(*vm.call("extra", &[])?, [Value::I32(7)]);
+
+    // Submemory 2 is untouched.
+    vm.select_submemory(2)?;
+    assert_eq!(*vm.call("increment", &[])?, [Value::I32(52)]);
+
+    // The free list is empty again.
+    assert_eq!(vm.add_submemory()?.0, 3);
+
+    Ok(())
+}
+
+#[test]
+fn removed_index() -> TestResult {
+    let wasm = parse_wat(SUBMEMORY_WAT)?;
+    let wasm = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE)?;
+    let mut vm = VM::new(&wasm)?;
+    for i in 0..2 {
+        assert_eq!(vm.add_submemory()?.0, i);
+    }
+    vm.remove_submemory(0)?;
+    assert!(vm.select_submemory(0).is_err());
+    assert!(vm.remove_submemory(0).is_err());
+    vm.select_submemory(1)?;
+
+    // Indices that were never added are rejected too.
+    assert!(vm.select_submemory(2).is_err());
+    assert!(vm.remove_submemory(2).is_err());
+
+    assert_eq!(vm.add_submemory()?.0, 0);
+    vm.select_submemory(0)?;
+
+    Ok(())
+}
+
+#[test]
+fn remove_current() -> TestResult {
+    let wasm = parse_wat(
+        r#"
+(module
+  (type (;0;) (func (result i32)))
+  (func $push (type 0) (result i32)
+    global.get 0
+    i32.const 16
+    i32.sub
+    global.set 0
+    global.get 0)
+  (memory (;0;) 1)
+  (global (;0;) (mut i32) (i32.const 1024))
+  (export "memory" (memory 0))
+  (export "push" (func $push)))
+"#,
+    )?;
+    let wasm = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE)?;
+    let mut vm = VM::new(&wasm)?;
+    vm.add_submemory()?;
+    vm.add_submemory()?;
+    vm.select_submemory(1)?;
+    assert_eq!(*vm.call("push", &[])?, [Value::I32(1008)]);
+    vm.remove_submemory(1)?;
+    assert_eq!(*vm.call("current_submemory", &[])?, [Value::I32(-2)]);
+
+    // Selecting another submemory must not save the removed submemory's
+    // globals over the record of the submemory reusing its slot.
+    assert_eq!(vm.add_submemory()?.0, 1);
+    vm.select_submemory(0)?;
+    vm.select_submemory(1)?;
+    assert_eq!(*vm.call("push", &[])?, [Value::I32(1008)]);
+
+    Ok(())
+}
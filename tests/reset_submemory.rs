@@ -0,0 +1,62 @@
+mod common;
+
+use crate::common::*;
+use testresult::TestResult;
+use wasmer::Value;
+
+fn dirty(vm: &mut VM, index: u32) -> anyhow::Result<()> {
+    vm.select_submemory(index)?;
+    assert_eq!(*vm.call("increment", &[])?, [Value::I32(43)]);
+    assert_eq!(*vm.call("increment", &[])?, [Value::I32(45)]);
+    assert_eq!(*vm.call("grow", &[])?, [Value::I32(2)]);
+    assert_eq!(*vm.call("extra", &[])?, [Value::I32(7)]);
+    assert_eq!(*vm.call("init", &[])?, [Value::I32(5)]);
+    assert!(vm.call("init", &[]).is_err());
+    Ok(())
+}
+
+#[test]
+fn full_reset() -> TestResult {
+    let wasm = parse_wat(SUBMEMORY_WAT)?;
+    let wasm = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE)?;
+    let mut vm = VM::new(&wasm)?;
+    for i in 0..2 {
+        assert_eq!(vm.add_submemory()?.0, i);
+        dirty(&mut vm, i)?;
+    }
+
+    // Reset the selected submemory as well as one that is not selected.
+    vm.select_submemory(1)?;
+    for i in [1, 0] {
+        vm.reset_submemory(i)?;
+        vm.select_submemory(i)?;
+        assert_eq!(*vm.call("extra", &[])?, [Value::I32(-1)]);
+        dirty(&mut vm, i)?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn other_submemories() -> TestResult {
+    let wasm = parse_wat(SUBMEMORY_WAT)?;
+    let wasm = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE)?;
+    let mut vm = VM::new(&wasm)?;
+    for i in 0..3 {
+        assert_eq!(vm.add_submemory()?.0, i);
+        dirty(&mut vm, i)?;
+    }
+    vm.reset_submemory(1)?;
+
+    for i in [0, 2] {
+        vm.select_submemory(i)?;
+        assert_eq!(*vm.call("extra", &[])?, [Value::I32(7)]);
+        assert_eq!(*vm.call("increment", &[])?, [Value::I32(48)]);
+    }
+
+    vm.remove_submemory(2)?;
+    assert!(vm.reset_submemory(2).is_err());
+    assert!(vm.reset_submemory(3).is_err());
+
+    Ok(())
+}
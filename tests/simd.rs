@@ -0,0 +1,96 @@
+mod common;
+
+use crate::common::*;
+use testresult::TestResult;
+use wasmer::Value;
+
+#[test]
+fn lane_counter() -> TestResult {
+    let wasm = parse_wat(
+        r#"
+(module
+  (type (;0;) (func (result i32)))
+  (func $entry (type 0) (result i32)
+    (local v128)
+    i32.const 0
+    i32.const 0
+    v128.const i32x4 0 0 0 0
+    v128.load32_lane offset=64 1
+    v128.const i32x4 0 1 0 0
+    i32x4.add
+    local.tee 0
+    v128.store32_lane offset=64 1
+    local.get 0
+    i32x4.extract_lane 1)
+  (memory (;0;) 1)
+  (export "memory" (memory 0))
+  (export "entry" (func $entry)))
+            "#,
+    )?;
+
+    let wasm = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE)?;
+    let mut vm = VM::new(&wasm)?;
+    for i in 0..10 {
+        assert_eq!(vm.add_submemory()?.0, i);
+    }
+    for i in 1..=10 {
+        for j in 0..10 {
+            vm.select_submemory(j)?;
+            let ret = vm.call("entry", &[])?;
+            assert_eq!(*ret, [Value::I32(i)], "{} {}", i, j);
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn extended_loads() -> TestResult {
+    let wasm = parse_wat(
+        r#"
+(module
+  (type (;0;) (func (result i32)))
+  (func $entry (type 0) (result i32)
+    i32.const 0
+    i32.const 0
+    i32.load offset=64
+    i32.const 1
+    i32.add
+    i32.store offset=64
+    i32.const 0
+    v128.load32_splat offset=64
+    i32x4.extract_lane 3
+    i32.const 0
+    v128.load32_zero offset=64
+    i32x4.extract_lane 0
+    i32.add
+    i32.const 0
+    v128.load8x8_s offset=64
+    i16x8.extract_lane_s 0
+    i32.add
+    i32.const 0
+    v128.load64_splat offset=64
+    i64x2.extract_lane 1
+    i32.wrap_i64
+    i32.add)
+  (memory (;0;) 1)
+  (export "memory" (memory 0))
+  (export "entry" (func $entry)))
+            "#,
+    )?;
+
+    let wasm = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE)?;
+    let mut vm = VM::new(&wasm)?;
+    for i in 0..10 {
+        assert_eq!(vm.add_submemory()?.0, i);
+    }
+    for i in 1..=10 {
+        for j in 0..10 {
+            vm.select_submemory(j)?;
+            let ret = vm.call("entry", &[])?;
+            assert_eq!(*ret, [Value::I32(4 * i)], "{} {}", i, j);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,177 @@
+mod common;
+
+use crate::common::*;
+use testresult::TestResult;
+use wasm_submemory::{BoundsMode, Metadata, RewriteOptions};
+use wasmer::Value;
+
+const WAT: &str = r#"
+(module
+  (type (;0;) (func (param i32) (result i32)))
+  (type (;1;) (func (param i32 i32)))
+  (type (;2;) (func (param i32 i32 i32)))
+  (func $load (type 0) (param i32) (result i32)
+    local.get 0
+    i32.load)
+  (func $store (type 1) (param i32 i32)
+    local.get 0
+    local.get 1
+    i32.store offset=8)
+  (func $grow (type 0) (param i32) (result i32)
+    local.get 0
+    memory.grow
+    drop
+    memory.size)
+  (func $fill (type 2) (param i32 i32 i32)
+    local.get 0
+    local.get 1
+    local.get 2
+    memory.fill)
+  (memory (;0;) 1)
+  (export "memory" (memory 0))
+  (export "load" (func $load))
+  (export "store" (func $store))
+  (export "grow" (func $grow))
+  (export "fill" (func $fill)))
+"#;
+
+#[test]
+fn counts() -> TestResult {
+    let wasm = parse_wat(WAT)?;
+    let options = RewriteOptions::new(SUBMEMORY_SIZE);
+    let (output, metadata, stats) = wasm_submemory::rewrite_with_stats(&wasm, &options)?;
+
+    let functions: Vec<_> = stats
+        .functions()
+        .iter()
+        .map(|function| {
+            let counts = function.counts();
+            (
+                function.name(),
+                counts.loads(),
+                counts.stores(),
+                counts.memory_sizes(),
+                counts.memory_grows(),
+                counts.bulk_memory(),
+                counts.instrs_before(),
+                counts.instrs_after(),
+            )
+        })
+        .collect();
+    // A load expands to seven instructions and a store to nine.
+    assert_eq!(
+        functions,
+        [
+            ("load", 1, 0, 0, 0, 0, 2, 8),
+            ("store", 0, 1, 0, 0, 0, 3, 11),
+            ("grow", 0, 0, 1, 1, 0, 4, 4),
+            ("fill", 0, 0, 0, 0, 1, 4, 4),
+        ]
+    );
+
+    let totals = stats.totals();
+    assert_eq!(totals.loads(), 1);
+    assert_eq!(totals.stores(), 1);
+    assert_eq!(totals.atomics(), 0);
+    assert_eq!(totals.memory_sizes(), 1);
+    assert_eq!(totals.memory_grows(), 1);
+    assert_eq!(totals.bulk_memory(), 1);
+    assert_eq!(totals.instrs_before(), 13);
+    assert_eq!(totals.instrs_after(), 27);
+
+    // Each function body is a size byte followed by the locals count and the
+    // instructions, and the rewritten ones grow with the accesses in them.
+    let sizes: Vec<_> = stats
+        .functions()
+        .iter()
+        .map(|function| {
+            let counts = function.counts();
+            (counts.size_before(), counts.size_after())
+        })
+        .collect();
+    assert_eq!(sizes[0].0, 8);
+    assert!(sizes[0].1 > sizes[0].0);
+    assert!(sizes[1].1 > sizes[1].0);
+    assert_eq!(
+        totals.size_before(),
+        sizes.iter().map(|&(before, _)| before).sum::<usize>()
+    );
+    assert_eq!(
+        totals.size_after(),
+        sizes.iter().map(|&(_, after)| after).sum::<usize>()
+    );
+    // The code section holds the function count and the guest functions,
+    // and after rewriting also the injected functions.
+    assert_eq!(stats.input_code_size(), 1 + totals.size_before());
+    assert!(stats.output_code_size() > 1 + totals.size_after());
+
+    assert_eq!(stats.input_size(), wasm.len());
+    assert_eq!(stats.output_size(), output.len());
+    assert!(stats.input_code_size() > 0);
+    assert!(stats.output_code_size() > stats.input_code_size());
+
+    assert_eq!(metadata, Metadata::from_wasm(&output)?);
+
+    // The rewritten module is the same as without statistics.
+    assert_eq!(
+        output,
+        wasm_submemory::rewrite_with_options(&wasm, &options)?
+    );
+    let mut vm = VM::new(&output)?;
+    vm.add_submemory()?;
+    vm.select_submemory(0)?;
+    vm.call("store", &[Value::I32(0), Value::I32(42)])?;
+    assert_eq!(*vm.call("load", &[Value::I32(8)])?, [Value::I32(42)]);
+
+    Ok(())
+}
+
+#[test]
+fn trap_mode() -> TestResult {
+    let wasm = parse_wat(WAT)?;
+    let options = RewriteOptions::new(SUBMEMORY_SIZE).bounds_mode(BoundsMode::Trap);
+    let (_, _, stats) = wasm_submemory::rewrite_with_stats(&wasm, &options)?;
+    let totals = stats.totals();
+    assert_eq!(totals.loads(), 1);
+    assert_eq!(totals.stores(), 1);
+    // The bounds checks add instructions, including in their trap blocks.
+    assert!(totals.instrs_after() > 27);
+
+    Ok(())
+}
+
+#[test]
+fn simd_lanes() -> TestResult {
+    let wasm = parse_wat(
+        r#"
+(module
+  (type (;0;) (func (param i32) (result v128)))
+  (type (;1;) (func (param i32 v128)))
+  (func $load_lane (type 0) (param i32) (result v128)
+    local.get 0
+    v128.const i32x4 0 0 0 0
+    v128.load32_lane 1)
+  (func $store_lane (type 1) (param i32 v128)
+    local.get 0
+    local.get 1
+    v128.store64_lane 0)
+  (memory (;0;) 1)
+  (export "memory" (memory 0))
+  (export "load_lane" (func $load_lane))
+  (export "store_lane" (func $store_lane)))
+"#,
+    )?;
+    let options = RewriteOptions::new(SUBMEMORY_SIZE);
+    let (_, _, stats) = wasm_submemory::rewrite_with_stats(&wasm, &options)?;
+    let functions: Vec<_> = stats
+        .functions()
+        .iter()
+        .map(|function| {
+            let counts = function.counts();
+            (function.name(), counts.loads(), counts.stores())
+        })
+        .collect();
+    assert_eq!(functions, [("load_lane", 1, 0), ("store_lane", 0, 1)]);
+
+    Ok(())
+}
@@ -0,0 +1,163 @@
+mod common;
+
+use crate::common::*;
+use testresult::TestResult;
+use wasm_submemory::{Error, Layout, Metadata, RewriteOptions, DEFAULT_MAX_SUBMEMORIES};
+use wasmer::Value;
+
+const WAT: &str = r#"
+(module
+  (type (;0;) (func (result i32)))
+  (func $entry (type 0) (result i32)
+    i32.const 0
+    i32.load offset=64)
+  (memory (;0;) 1)
+  (data $.data (i32.const 64) "*\00\00\00")
+  (export "memory" (memory 0))
+  (export "entry" (func $entry)))
+"#;
+
+#[test]
+fn max_submemories() -> TestResult {
+    let wasm = parse_wat(WAT)?;
+    let options = RewriteOptions::new(WASM_PAGE_SIZE).max_submemories(3);
+    let wasm = wasm_submemory::rewrite_with_options(&wasm, &options)?;
+    let mut vm = VM::new(&wasm)?;
+    for i in 0..3 {
+        assert_eq!(vm.add_submemory()?.0, i);
+    }
+    assert!(vm.add_submemory().is_err());
+
+    for i in 0..3 {
+        vm.select_submemory(i)?;
+        assert_eq!(*vm.call("entry", &[])?, [Value::I32(42)]);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn headroom_sized_from_maximum() -> TestResult {
+    let wasm = parse_wat(WAT)?;
+    let options = RewriteOptions::new(WASM_PAGE_SIZE).max_submemories(100_000);
+    let wasm = wasm_submemory::rewrite_with_options(&wasm, &options)?;
+    let mut vm = VM::new(&wasm)?;
+
+    // 100,001 records of 12 bytes need 19 pages of headroom.
+    let base_address = |i| WASM_PAGE_SIZE * 20 + WASM_PAGE_SIZE * i;
+    for i in 0..10 {
+        assert_eq!(vm.add_submemory()?, (i, base_address(i)));
+    }
+    vm.select_submemory(9)?;
+    assert_eq!(*vm.call("entry", &[])?, [Value::I32(42)]);
+
+    let options = RewriteOptions::new(WASM_PAGE_SIZE).max_submemories(u32::MAX);
+    let ret = wasm_submemory::rewrite_with_options(&parse_wat(WAT)?, &options);
+    assert!(matches!(ret, Err(Error::HeadroomTooLarge { .. })));
+
+    Ok(())
+}
+
+#[test]
+fn full_headroom() -> TestResult {
+    // Sixteen v128 globals make each record 272 bytes, so a one-page
+    // headroom holds the template record and 239 submemory records.
+    let globals = (0..16)
+        .map(|i| format!("(global $g{} (mut v128) (v128.const i32x4 {} 0 0 0))", i, i))
+        .collect::<String>();
+    let wat = format!(
+        r#"
+(module
+  (type (;0;) (func (result i32)))
+  (func $entry (type 0) (result i32)
+    i32.const 0
+    i32.load offset=64
+    global.get $g15
+    i32x4.extract_lane 0
+    i32.add)
+  {}
+  (memory (;0;) 1)
+  (data $.data (i32.const 64) "*\00\00\00")
+  (export "memory" (memory 0))
+  (export "entry" (func $entry)))
+"#,
+        globals
+    );
+    let wasm = parse_wat(&wat)?;
+    let options = RewriteOptions::new(WASM_PAGE_SIZE).headroom_size(WASM_PAGE_SIZE);
+    let wasm = wasm_submemory::rewrite_with_options(&wasm, &options)?;
+    let mut vm = VM::new(&wasm)?;
+    for i in 0..239 {
+        assert_eq!(vm.add_submemory()?.0, i);
+    }
+    assert!(vm.add_submemory().is_err());
+
+    // The template record and the initial image are intact.
+    for i in [0, 238] {
+        vm.select_submemory(i)?;
+        assert_eq!(*vm.call("entry", &[])?, [Value::I32(57)]);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn address_space() -> TestResult {
+    let wasm = parse_wat(WAT)?;
+    // Only three 1 GiB submemories fit after the headroom and initial image.
+    let wasm = wasm_submemory::rewrite(&wasm, 1 << 30)?;
+    let mut vm = VM::new(&wasm)?;
+    for i in 0..3 {
+        assert_eq!(vm.add_submemory()?, (i, WASM_PAGE_SIZE * 2 + (1 << 30) * i));
+    }
+    assert_eq!(
+        *vm.call("add_submemory", &[])?,
+        [Value::I32(-1), Value::I32(-1)]
+    );
+    assert!(vm.add_submemory().is_err());
+
+    for i in 0..3 {
+        vm.select_submemory(i)?;
+        assert_eq!(*vm.call("entry", &[])?, [Value::I32(42)]);
+    }
+    assert!(vm.select_submemory(3).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn default_capacity() -> TestResult {
+    // 16,385 records of 12 bytes need 4 pages of headroom.
+    let wasm = wasm_submemory::rewrite(&parse_wat(WAT)?, WASM_PAGE_SIZE)?;
+    let metadata = Metadata::from_wasm(&wasm)?;
+    assert_eq!(metadata.max_submemories(), DEFAULT_MAX_SUBMEMORIES);
+    assert_eq!(metadata.layout().headroom_size(), WASM_PAGE_SIZE * 4);
+    let mut vm = VM::new(&wasm)?;
+    assert_eq!(vm.add_submemory()?, (0, WASM_PAGE_SIZE * 5));
+
+    // Larger submemories are limited by the address space instead, and keep
+    // a single page of headroom.
+    let wasm = wasm_submemory::rewrite(&parse_wat(WAT)?, SUBMEMORY_SIZE)?;
+    let metadata = Metadata::from_wasm(&wasm)?;
+    assert_eq!(metadata.max_submemories(), 4095);
+    assert_eq!(metadata.layout().headroom_size(), WASM_PAGE_SIZE);
+
+    Ok(())
+}
+
+#[test]
+fn too_large() -> TestResult {
+    let wasm = parse_wat(WAT)?;
+    let options = RewriteOptions::new(WASM_PAGE_SIZE).headroom_size(WASM_PAGE_SIZE * 65535);
+    let ret = wasm_submemory::rewrite_with_options(&wasm, &options);
+    assert!(matches!(
+        ret,
+        Err(Error::HeadroomTooLarge {
+            initial_pages: 1,
+            ..
+        })
+    ));
+    assert!(Layout::new(WASM_PAGE_SIZE, WASM_PAGE_SIZE * 65535, 1).is_err());
+
+    Ok(())
+}
@@ -0,0 +1,91 @@
+mod common;
+
+use crate::common::*;
+use testresult::TestResult;
+use wasm_submemory::{BoundsMode, Error, RewriteOptions};
+use wasmer::Value;
+
+const SIZE: u32 = 3 << 20;
+
+const WAT: &str = r#"
+(module
+  (type (;0;) (func (param i32) (result i32)))
+  (type (;1;) (func (param i32 i32)))
+  (func $load (type 0) (param i32) (result i32)
+    local.get 0
+    i32.load)
+  (func $store (type 1) (param i32 i32)
+    local.get 0
+    local.get 1
+    i32.store)
+  (memory (;0;) 1)
+  (export "memory" (memory 0))
+  (export "load" (func $load))
+  (export "store" (func $store)))
+"#;
+
+#[test]
+fn non_power_of_two() -> TestResult {
+    let wasm = parse_wat(WAT)?;
+
+    for bounds_mode in [BoundsMode::Mask, BoundsMode::Trap] {
+        let wasm = wasm_submemory::rewrite_with_options(
+            &wasm,
+            &RewriteOptions::new(SIZE).bounds_mode(bounds_mode),
+        )?;
+        let mut vm = VM::new(&wasm)?;
+        let base_address = |i| WASM_PAGE_SIZE * 2 + SIZE * i;
+        for i in 0..10 {
+            assert_eq!(vm.add_submemory()?, (i, base_address(i)));
+        }
+        for i in 0..10 {
+            vm.select_submemory(i)?;
+            vm.call("store", &[Value::I32(0), Value::I32(i as i32)])?;
+            vm.call(
+                "store",
+                &[Value::I32(SIZE as i32 - 4), Value::I32(-(i as i32))],
+            )?;
+        }
+        for i in 0..10 {
+            vm.select_submemory(i)?;
+            let ret = vm.call("load", &[Value::I32(0)])?;
+            assert_eq!(*ret, [Value::I32(i as i32)], "{:?} {}", bounds_mode, i);
+            let ret = vm.call("load", &[Value::I32(SIZE as i32 - 4)])?;
+            assert_eq!(*ret, [Value::I32(-(i as i32))], "{:?} {}", bounds_mode, i);
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn wraps_modulo_size() -> TestResult {
+    let wasm = parse_wat(WAT)?;
+    let wasm = wasm_submemory::rewrite(&wasm, SIZE)?;
+    let mut vm = VM::new(&wasm)?;
+    for i in 0..2 {
+        assert_eq!(vm.add_submemory()?.0, i);
+    }
+    vm.select_submemory(0)?;
+    vm.call("store", &[Value::I32(SIZE as i32 + 8), Value::I32(42)])?;
+    assert_eq!(*vm.call("load", &[Value::I32(8)])?, [Value::I32(42)]);
+    vm.select_submemory(1)?;
+    assert_eq!(*vm.call("load", &[Value::I32(8)])?, [Value::I32(0)]);
+
+    Ok(())
+}
+
+#[test]
+fn invalid() -> TestResult {
+    let wasm = parse_wat(WAT)?;
+    for size in [0, 1000, SIZE + 1, SIZE - WASM_PAGE_SIZE / 2] {
+        let ret = wasm_submemory::rewrite(&wasm, size);
+        assert!(
+            matches!(ret, Err(Error::InvalidSubmemorySize(s)) if s == size),
+            "{}",
+            size
+        );
+    }
+
+    Ok(())
+}
@@ -2,6 +2,7 @@ mod common;
 
 use crate::common::*;
 use testresult::TestResult;
+use wasmer::{imports, Global, Value};
 
 #[test]
 fn allowed() -> TestResult {
@@ -28,27 +29,135 @@ fn allowed() -> TestResult {
 }
 
 #[test]
-fn denied() -> TestResult {
+fn multiple_mutable() -> TestResult {
     let wasm = parse_wat(
         r#"
 (module
   (type (;0;) (func (result i32)))
   (func (;0;) (type 0) (result i32)
-    i32.const 20
+    global.get 0
+    i32.const 1
+    i32.add
     global.set 0
-    i32.const 22
+    global.get 1
+    i64.const 2
+    i64.add
     global.set 1
+    global.get 2
+    f64.const 3
+    f64.add
+    global.set 2
     global.get 0
     global.get 1
+    i32.wrap_i64
+    i32.add
+    global.get 2
+    i32.trunc_f64_s
     i32.add)
   (memory (;0;) 1)
   (global (;0;) (mut i32) (i32.const 0))
-  (global (;1;) (mut i32) (i32.const 0))
+  (global (;1;) (mut i64) (i64.const 100))
+  (global (;2;) (mut f64) (f64.const 1000))
+  (export "memory" (memory 0))
+  (export "entry" (func 0)))
+  "#,
+    )?;
+
+    let wasm = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE)?;
+    let mut vm = VM::new(&wasm)?;
+    for i in 0..10 {
+        assert_eq!(vm.add_submemory()?.0, i);
+    }
+    for i in 1..=10 {
+        for j in 0..10 {
+            vm.select_submemory(j)?;
+            let ret = vm.call("entry", &[])?;
+            assert_eq!(*ret, [Value::I32(1100 + 6 * i)], "{} {}", i, j);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn imported_mutable() -> TestResult {
+    let wasm = parse_wat(
+        r#"
+(module
+  (type (;0;) (func (result i32)))
+  (import "env" "counter" (global (;0;) (mut i32)))
+  (func (;0;) (type 0) (result i32)
+    global.get 0
+    i32.const 1
+    i32.add
+    global.set 0
+    global.get 0)
+  (memory (;0;) 1)
   (export "memory" (memory 0))
   (export "entry" (func 0)))
   "#,
     )?;
 
-    assert!(wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE).is_err());
+    let wasm = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE)?;
+    let mut counter = None;
+    let mut vm = VM::with_imports(&wasm, |store| {
+        let global = Global::new_mut(store, Value::I32(42));
+        counter = Some(global.clone());
+        imports! {
+            "env" => {
+                "counter" => global,
+            },
+        }
+    })?;
+    let counter = counter.unwrap();
+    for i in 0..10 {
+        assert_eq!(vm.add_submemory()?.0, i);
+    }
+    for i in 1..=10 {
+        for j in 0..10 {
+            vm.select_submemory(j)?;
+            let ret = vm.call("entry", &[])?;
+            assert_eq!(*ret, [Value::I32(42 + i)], "{} {}", i, j);
+            assert_eq!(counter.get(&mut vm.store), Value::I32(42 + i));
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn set_by_start_function() -> TestResult {
+    // Like AssemblyScript's runtime globals, initialized by the start
+    // function.
+    let wasm = parse_wat(
+        r#"
+(module
+  (type (;0;) (func))
+  (type (;1;) (func (result i32)))
+  (func $start (type 0)
+    i32.const 1000
+    global.set 0)
+  (func $entry (type 1) (result i32)
+    global.get 0
+    i32.const 1
+    i32.add
+    global.set 0
+    global.get 0)
+  (memory (;0;) 1)
+  (global (;0;) (mut i32) (i32.const 0))
+  (start $start)
+  (export "memory" (memory 0))
+  (export "entry" (func $entry)))
+  "#,
+    )?;
+
+    let wasm = wasm_submemory::rewrite(&wasm, SUBMEMORY_SIZE)?;
+    let mut vm = VM::new(&wasm)?;
+    for i in 0..3 {
+        vm.add_submemory()?;
+        vm.select_submemory(i)?;
+        for j in 1..=i + 1 {
+            assert_eq!(*vm.call("entry", &[])?, [Value::I32(1000 + j as i32)]);
+        }
+    }
+
     Ok(())
 }